@@ -0,0 +1,220 @@
+"""
+Check the files Icarust writes can be opened by ONT's own readers, and that every read in them reads back the way Icarust
+wrote it.
+
+Icarust writes POD5, SLOW5 and BLOW5 files itself rather than through the pod5 library or slow5lib, so this opens each
+one with the official `pod5` or `pyslow5` package and checks the run info and every read against what Icarust writes.
+"""
+import argparse
+import logging
+import sys
+from pathlib import Path
+from typing import List
+from uuid import UUID
+
+from rich.logging import RichHandler
+
+logger = logging.getLogger(__name__)
+logger.addHandler(RichHandler())
+logger.setLevel(logging.INFO)
+
+# The calibration every Icarust output file is written with, see src/output.rs
+DIGITISATION = 8192
+CALIBRATION_OFFSET = 6.0
+CALIBRATION_RANGE = 1500.0
+MEDIAN_BEFORE = 100.0
+END_REASONS = {
+    "unknown",
+    "mux_change",
+    "unblock_mux_change",
+    "data_service_unblock_mux_change",
+    "signal_positive",
+    "signal_negative",
+    "api_request",
+}
+
+
+def check_read_id(read_id: str, seen: set, errors: List[str], file: Path) -> None:
+    """
+    Check a read id is a UUID that hasn't been seen before in any file
+
+    Parameters
+    ----------
+    read_id: str
+        The read id
+    seen: set
+        Read ids already seen
+    errors: List[str]
+        Any problem found is added to this
+    file: Path
+        The file the read is in, for the error message
+    """
+    try:
+        UUID(read_id)
+    except ValueError:
+        errors.append(f"{file}: read id {read_id} is not a UUID")
+    if read_id in seen:
+        errors.append(f"{file}: read id {read_id} is written more than once")
+    seen.add(read_id)
+
+
+def check_pod5(file: Path, seen: set) -> List[str]:
+    """
+    Open a POD5 file with the pod5 reader, and check the run info and every read.
+
+    Parameters
+    ----------
+    file: Path
+        The POD5 file
+    seen: set
+        Read ids seen in earlier files
+
+    Returns
+    -------
+    List[str]
+        Problems found in the file
+    """
+    import pod5
+
+    errors = []
+    num_reads = 0
+    with pod5.Reader(file) as reader:
+        for read in reader.reads():
+            num_reads += 1
+            read_id = str(read.read_id)
+            check_read_id(read_id, seen, errors, file)
+            signal = read.signal
+            run_info = read.run_info
+            if len(signal) != read.num_samples:
+                errors.append(f"{file}: {read_id} has {len(signal)} samples, but num_samples is {read.num_samples}")
+            if abs(read.calibration.offset - CALIBRATION_OFFSET) > 1e-6:
+                errors.append(f"{file}: {read_id} has calibration offset {read.calibration.offset}")
+            if abs(read.calibration.scale - CALIBRATION_RANGE / DIGITISATION) > 1e-6:
+                errors.append(f"{file}: {read_id} has calibration scale {read.calibration.scale}")
+            if abs(read.median_before - MEDIAN_BEFORE) > 1e-6:
+                errors.append(f"{file}: {read_id} has median before {read.median_before}")
+            if read.end_reason.name not in END_REASONS:
+                errors.append(f"{file}: {read_id} has an unknown end reason {read.end_reason.name}")
+            if read.pore.channel < 1:
+                errors.append(f"{file}: {read_id} is on channel {read.pore.channel}")
+            if len(signal) and (signal.min() < run_info.adc_min or signal.max() > run_info.adc_max):
+                errors.append(f"{file}: {read_id} has signal outside of the ADC range")
+            if str(run_info.sample_rate) != run_info.context_tags.get("sample_frequency"):
+                errors.append(f"{file}: sample rate {run_info.sample_rate} doesn't match the context tags")
+            if run_info.acquisition_id != run_info.tracking_id.get("run_id"):
+                errors.append(f"{file}: acquisition id {run_info.acquisition_id} doesn't match the run id")
+    if not num_reads:
+        errors.append(f"{file}: has no reads")
+    logger.info(f"{file}: {num_reads} reads")
+    return errors
+
+
+def check_slow5(file: Path, seen: set) -> List[str]:
+    """
+    Open a SLOW5 or BLOW5 file with pyslow5, and check the header and every read, including the auxiliary fields.
+
+    Parameters
+    ----------
+    file: Path
+        The SLOW5 or BLOW5 file
+    seen: set
+        Read ids seen in earlier files
+
+    Returns
+    -------
+    List[str]
+        Problems found in the file
+    """
+    import pyslow5
+
+    errors = []
+    num_reads = 0
+    s5 = pyslow5.Open(str(file), "r")
+    try:
+        end_reasons = s5.get_aux_enum_labels("end_reason")
+        sample_frequency = s5.get_header_value("sample_frequency")
+        run_id = s5.get_header_value("run_id")
+        if not run_id:
+            errors.append(f"{file}: has no run_id header attribute")
+        for read in s5.seq_reads(pA=False, aux="all"):
+            num_reads += 1
+            read_id = read["read_id"]
+            check_read_id(read_id, seen, errors, file)
+            if len(read["signal"]) != read["len_raw_signal"]:
+                errors.append(f"{file}: {read_id} has {len(read['signal'])} samples, but len_raw_signal is {read['len_raw_signal']}")
+            if read["read_group"] != 0:
+                errors.append(f"{file}: {read_id} is in read group {read['read_group']}")
+            for field, expected in [
+                ("digitisation", DIGITISATION),
+                ("offset", CALIBRATION_OFFSET),
+                ("range", CALIBRATION_RANGE),
+                ("median_before", MEDIAN_BEFORE),
+            ]:
+                if abs(read[field] - expected) > 1e-6:
+                    errors.append(f"{file}: {read_id} has {field} {read[field]}")
+            if str(int(read["sampling_rate"])) != sample_frequency:
+                errors.append(f"{file}: {read_id} has sampling rate {read['sampling_rate']}, but the header has {sample_frequency}")
+            if int(read["channel_number"]) < 1:
+                errors.append(f"{file}: {read_id} is on channel {read['channel_number']}")
+            if end_reasons[read["end_reason"]] not in END_REASONS:
+                errors.append(f"{file}: {read_id} has an unknown end reason {end_reasons[read['end_reason']]}")
+    finally:
+        s5.close()
+    if not num_reads:
+        errors.append(f"{file}: has no reads")
+    logger.info(f"{file}: {num_reads} reads")
+    return errors
+
+
+CHECKS = {
+    ".pod5": check_pod5,
+    ".slow5": check_slow5,
+    ".blow5": check_slow5,
+}
+
+
+def output_files(paths: List[Path]) -> List[Path]:
+    """
+    Find every output file in the given files and directories, searching directories recursively
+
+    Parameters
+    ----------
+    paths: List[Path]
+        Files, or directories such as an Icarust output directory
+
+    Returns
+    -------
+    List[Path]
+        The output files found, sorted
+    """
+    files = []
+    for path in paths:
+        if path.is_dir():
+            files.extend(file for file in path.rglob("*") if file.suffix in CHECKS)
+        elif path.suffix in CHECKS:
+            files.append(path)
+        else:
+            logger.warning(f"Skipping {path}, which isn't a file Icarust can check")
+    return sorted(files)
+
+
+if __name__ == "__main__":
+    parser = argparse.ArgumentParser(description="Check Icarust output files can be read by ONT's readers.")
+    parser.add_argument("paths", metavar="paths", type=Path, nargs="+",
+                        help="Output files, or directories to search for them, such as pod5_pass or blow5_pass.")
+    args = parser.parse_args()
+    files = output_files(args.paths)
+    if not files:
+        logger.error("No output files found")
+        sys.exit(1)
+    seen = set()
+    errors = []
+    for file in files:
+        try:
+            errors.extend(CHECKS[file.suffix](file, seen))
+        except Exception as err:
+            errors.append(f"{file}: failed to read - {err}")
+    for error in errors:
+        logger.error(error)
+    logger.info(f"Checked {len(seen)} reads in {len(files)} files, {len(errors)} problems found")
+    sys.exit(1 if errors else 0)
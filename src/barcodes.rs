@@ -0,0 +1,163 @@
+//! Barcoding kits, and the sequence that a barcode adds to each end of a read.
+//!
+//! Each kit places its barcodes between flanking sequences. Native and PCR barcodes are ligated or amplified onto both ends
+//! of the strand, so the barcode is read at the start and its reverse complement at the end. Rapid barcodes are attached by
+//! transposase, so only the start of the read is barcoded. The sequences for barcodes 1 to 24 are built in, any others
+//! have to be given in the `custom_barcodes` table of the simulation profile. The 96 barcode kits aren't supported, as
+//! most of their barcodes aren't built in.
+//!
+
+/// The sequences of barcodes 1 to 24, shared by the native, rapid and PCR barcoding kits. Native barcodes are read as the
+/// reverse complement of these at the start of the read.
+const BARCODE_SEQUENCES: [&str; 24] = [
+    "AAGAAAGTTGTCGGTGTCTTTGTG",
+    "TCGATTCCGTTTGTAGTCGTCTGT",
+    "GAGTCTTGTGTCCCAGTTACCAGG",
+    "TTCGGATTCTATCGTGTTTCCCTA",
+    "CTTGTCCAGGGTTTGTGTAACCTT",
+    "TTCTCGCAAAGGCAGAAAGTAGTC",
+    "GTGTTACCGTGGGAATGAATCCTT",
+    "TTCAGGGAACAAACCAAGTTACGT",
+    "AACTAGGCACAGCGAGTCTTGGTT",
+    "AAGCGTTGAAACCTTTGTCCTCTC",
+    "GTTTCATCTATCGGAGGGAATGGA",
+    "CAGGTAGAAAGAAGCAGAATCGGA",
+    "AGAACGACTTCCATACTCGTGTGA",
+    "AACGAGTCTCTTGGGACCCATAGA",
+    "AGGTCTACCTCGCTAACACCACTG",
+    "CGTCAACTGACAGTGGTTCGTACT",
+    "ACCCTCCAGGAAAGTACCTCTGAT",
+    "CCAAACCCAACAACCTAGATAGGC",
+    "GTTCCTCGTGCAGTGTCAAGAGAT",
+    "TTGCGTCCTGTTACGAGAACTCAT",
+    "GAGCCTCTCATTGTCCGTTCTCTA",
+    "ACCACTGCCATGTATCAAAGTACG",
+    "CTTACTACCCAGAACACACCGGAG",
+    "GCATAGTTCTGCATGATGGGTTAG",
+];
+
+/// A barcoding kit - which barcodes it has and how they sit on the read
+#[derive(Debug)]
+pub struct BarcodeKit {
+    /// Sequencing and expansion kits that use these barcodes
+    pub names: &'static [&'static str],
+    /// The number of barcodes in the kit
+    pub num_barcodes: usize,
+    /// Sequence before and after the barcode at the start of the read
+    pub front_flanks: (&'static str, &'static str),
+    /// Sequence before and after the barcode at the end of the read, None if only the start of the read is barcoded
+    pub rear_flanks: Option<(&'static str, &'static str)>,
+    /// Whether the barcode at the start of the read is the reverse complement of the sequence in BARCODE_SEQUENCES. The
+    /// barcode at the end of the read is always the other way round.
+    pub front_reverse_complement: bool,
+}
+
+const NATIVE_FRONT_FLANKS: (&str, &str) = ("AAGGTTAA", "CAGCACCT");
+const NATIVE_REAR_FLANKS: (&str, &str) = ("AGGTGCTG", "TTAACCTTAGCAAT");
+const RAPID_FRONT_FLANKS: (&str, &str) = (
+    "GCTTGGGTGTTTAACC",
+    "GTTTTCGCATTTATCGTGAAACGCTTTCGCGTTTTTCGTGCGCCGCTTCA",
+);
+const PCR_FRONT_FLANKS: (&str, &str) = ("GGTGCTG", "TTAACCT");
+const PCR_REAR_FLANKS: (&str, &str) = ("AGGTTAA", "CAGCACC");
+
+/// Native barcoding with 24 barcodes
+pub const NATIVE_24: BarcodeKit = BarcodeKit {
+    names: &["SQK-NBD114-24", "SQK-NBD112-24", "EXP-NBD104", "EXP-NBD114"],
+    num_barcodes: 24,
+    front_flanks: NATIVE_FRONT_FLANKS,
+    rear_flanks: Some(NATIVE_REAR_FLANKS),
+    front_reverse_complement: true,
+};
+
+/// Rapid barcoding with 24 barcodes
+pub const RAPID_24: BarcodeKit = BarcodeKit {
+    names: &["SQK-RBK114-24", "SQK-RBK111-24"],
+    num_barcodes: 24,
+    front_flanks: RAPID_FRONT_FLANKS,
+    rear_flanks: None,
+    front_reverse_complement: false,
+};
+
+/// PCR barcoding with 24 barcodes
+pub const PCR_24: BarcodeKit = BarcodeKit {
+    names: &["SQK-PCB114-24", "SQK-PCB111-24"],
+    num_barcodes: 24,
+    front_flanks: PCR_FRONT_FLANKS,
+    rear_flanks: Some(PCR_REAR_FLANKS),
+    front_reverse_complement: false,
+};
+
+/// Every kit we know about
+const KITS: [&BarcodeKit; 3] = [&NATIVE_24, &RAPID_24, &PCR_24];
+
+/// 96 barcode kits, which can't be simulated as only barcodes 1 to 24 are built in
+const UNSUPPORTED_KITS: [&str; 6] = [
+    "SQK-NBD114-96",
+    "SQK-NBD112-96",
+    "EXP-NBD196",
+    "SQK-RBK114-96",
+    "SQK-RBK110-96",
+    "SQK-RBK111-96",
+];
+
+/// Find a barcoding kit by name, ignoring case.
+pub fn find_kit(name: &str) -> Option<&'static BarcodeKit> {
+    KITS.iter()
+        .find(|kit| kit.names.iter().any(|x| x.eq_ignore_ascii_case(name)))
+        .copied()
+}
+
+/// Whether a kit is a barcoding kit we know of but can't simulate, ignoring case.
+pub fn is_unsupported_kit(name: &str) -> bool {
+    UNSUPPORTED_KITS
+        .iter()
+        .any(|x| x.eq_ignore_ascii_case(name))
+}
+
+impl BarcodeKit {
+    /// Look up the sequence of a barcode by name. Any name ending in the barcode number works, so barcode01, NB01 and
+    /// RB01 are all barcode 1.
+    pub fn barcode_sequence(&self, name: &str) -> Option<&'static str> {
+        let digits = name.len() - name.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+        let number: usize = name[name.len() - digits..].parse().ok()?;
+        match number {
+            1.. if number <= self.num_barcodes => BARCODE_SEQUENCES.get(number - 1).copied(),
+            _ => None,
+        }
+    }
+
+    /// The sequence a barcode adds to the start and end of the read, the end is empty if the kit only barcodes the start.
+    pub fn flanked_sequences(&self, barcode_sequence: &str) -> (String, String) {
+        let (forward, reverse) = (
+            barcode_sequence.to_string(),
+            reverse_complement(barcode_sequence),
+        );
+        let (front_barcode, rear_barcode) = match self.front_reverse_complement {
+            true => (reverse, forward),
+            false => (forward, reverse),
+        };
+        let (before, after) = self.front_flanks;
+        let front = format!("{before}{front_barcode}{after}");
+        let rear = match self.rear_flanks {
+            Some((before, after)) => format!("{before}{rear_barcode}{after}"),
+            None => String::new(),
+        };
+        (front, rear)
+    }
+}
+
+/// Reverse complement a sequence.
+pub fn reverse_complement(sequence: &str) -> String {
+    sequence
+        .chars()
+        .rev()
+        .map(|base| match base.to_ascii_uppercase() {
+            'A' => 'T',
+            'C' => 'G',
+            'G' => 'C',
+            'T' => 'A',
+            other => other,
+        })
+        .collect()
+}
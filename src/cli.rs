@@ -1,15 +1,19 @@
-//! Implementation of the CLI for icarust. Simple - just lets a user provide verbosity and a path to a config toml.
+//! Implementation of the CLI for icarust. Simple - just lets a user provide verbosity and a path to a config toml, or
+//! run the squiggle subcommand to prepare squiggle arrays.
 
 use crate::utils::set_up_logging;
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 
 #[derive(Parser, Debug, Clone)]
-#[clap(author, version, about, long_about = None)]
+#[clap(author, version, about, long_about = None, subcommand_negates_reqs = true)]
 /// Struct to capture the command line interface options
 pub struct Cli {
+    /// Run a subcommand instead of the simulated MinKNOW
+    #[clap(subcommand)]
+    pub command: Option<Command>,
     /// Path to the simulation profile toml file to use
-    #[clap(short, long, value_parser)]
-    pub simulation_profile: std::path::PathBuf,
+    #[clap(short, long, value_parser, required = true)]
+    pub simulation_profile: Option<std::path::PathBuf>,
     /// Determines verbosity of the processing, can be specified multiple times (max -vvv)
     #[structopt(short, long, parse(from_occurrences))]
     pub verbosity: u8,
@@ -39,8 +43,66 @@ impl Cli {
 
         set_up_logging(log_level);
     }
+    /// Get the path to the simulation profile, which clap requires unless a subcommand is given.
+    pub fn get_simulation_profile(&self) -> &std::path::PathBuf {
+        self.simulation_profile
+            .as_ref()
+            .expect("A simulation profile is required")
+    }
+
     /// Check the config file is where specified
     pub fn check_config_exists(&self) {
-        assert!(self.simulation_profile.exists())
+        assert!(self.get_simulation_profile().exists())
     }
 }
+
+/// Subcommands
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Simulate squiggle arrays and a distributions.json from reference sequences, to serve as samples
+    Squiggle(SquiggleArgs),
+}
+
+/// Options for the squiggle subcommand, which mirror python/make_squiggle.py
+#[derive(Args, Debug, Clone)]
+pub struct SquiggleArgs {
+    /// Reference FASTA or FASTQ files to convert to squiggle
+    #[clap(value_parser, required = true)]
+    pub reference_files: Vec<std::path::PathBuf>,
+    /// Primer scheme BED file. Splits the reference into amplicons, producing a squiggle array for each amplicon. Only works with one reference file
+    #[clap(long, value_parser)]
+    pub bed_file: Option<std::path::PathBuf>,
+    /// Directory to write the squiggle arrays to. Defaults to the current working directory
+    #[clap(long, value_parser, default_value = ".")]
+    pub out_dir: std::path::PathBuf,
+    /// Degree of skew applied to the relative rate contigs are seen at. 0 weights contigs by their length
+    #[clap(long, value_parser, default_value_t = 0.0)]
+    pub skew: f64,
+    /// Barcodes to add to each reference, in the format Barcode01 Barcode02. Must be one for each reference file
+    #[clap(long, value_parser, multiple_values = true)]
+    pub barcode: Option<Vec<String>>,
+    /// Directory of barcode FASTA files, each with the sequence for the start and end of the read
+    #[clap(long, value_parser, default_value = "python/barcoding/fasta")]
+    pub barcode_dir: std::path::PathBuf,
+    /// Pore model to simulate the squiggle with
+    #[clap(long, value_parser, default_value = "static/R10_model.tsv")]
+    pub pore_model: std::path::PathBuf,
+    /// Translocation speed of the simulated strands
+    #[clap(long, value_parser, default_value_t = 400.0)]
+    pub bases_per_second: f64,
+    /// Multiplier on the standard deviation of each kmer level, 0 for noiseless squiggle. Defaults to the level_noise of the
+    /// simulation profile if one is given, otherwise 0
+    #[clap(long, value_parser)]
+    pub level_noise: Option<f64>,
+    /// How many samples each base spends in the pore, one of fixed, gamma or exponential. Defaults to the
+    /// dwell_distribution of the simulation profile if one is given, otherwise fixed
+    #[clap(long, value_parser)]
+    pub dwell_distribution: Option<String>,
+    /// Standard deviation of the samples per base for gamma dwell. Defaults to the dwell_stdv of the simulation profile if
+    /// one is given, otherwise 40% of the mean
+    #[clap(long, value_parser)]
+    pub dwell_stdv: Option<f64>,
+    /// Seed for the simulated noise, random if not given
+    #[clap(long, value_parser)]
+    pub seed: Option<u64>,
+}
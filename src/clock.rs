@@ -0,0 +1,67 @@
+//! The simulation clock, which every timing decision is made against so a run can go faster than real time.
+//!
+//! The clock starts at the real time it is first used, and then runs `speed_up` times faster than real time. Read start
+//! times, chunk cadence, pauses between reads and the timestamps written out all use it, so a 48 hour run with a speed up
+//! of 10 takes under 5 hours. Sleeping for a simulated duration sleeps for that duration divided by the speed up.
+//!
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+
+static CLOCK: OnceLock<Clock> = OnceLock::new();
+
+/// A clock running at a multiple of real time
+#[derive(Debug)]
+struct Clock {
+    /// Real time the clock started at
+    started: Instant,
+    /// Simulated time the clock started at, the real time it started
+    started_utc: DateTime<Utc>,
+    speed_up: f64,
+}
+
+impl Clock {
+    fn new(speed_up: f64) -> Clock {
+        Clock {
+            started: Instant::now(),
+            started_utc: Utc::now(),
+            speed_up,
+        }
+    }
+}
+
+/// Start the simulation clock running `speed_up` times faster than real time. Must be called before the clock is first
+/// used, otherwise it runs at real time.
+pub fn start(speed_up: f64) {
+    match CLOCK.set(Clock::new(speed_up)) {
+        Ok(()) if speed_up != 1.0 => info!("Simulation clock running {speed_up}x real time"),
+        Ok(()) => {}
+        Err(_) => warn!("The simulation clock was already running, so its speed up is unchanged"),
+    }
+}
+
+fn clock() -> &'static Clock {
+    CLOCK.get_or_init(|| Clock::new(1.0))
+}
+
+/// The current simulated time.
+pub fn now() -> DateTime<Utc> {
+    let clock = clock();
+    let elapsed = clock.started.elapsed().mul_f64(clock.speed_up);
+    clock.started_utc
+        + chrono::Duration::from_std(elapsed).unwrap_or_else(|_| chrono::Duration::zero())
+}
+
+/// Simulated seconds since `since`.
+pub fn seconds_since(since: DateTime<Utc>) -> f64 {
+    (now() - since)
+        .num_microseconds()
+        .map_or(0.0, |micros| micros as f64 / 1_000_000.0)
+}
+
+/// Sleep for a simulated duration.
+pub fn sleep(duration: Duration) {
+    thread::sleep(duration.div_f64(clock().speed_up));
+}
@@ -14,14 +14,17 @@
 //!
 use futures::{Stream, StreamExt};
 use needletail::{parse_fastx_file, FastxReader};
+use std::borrow::Cow;
 use std::cmp::{self, min};
 use std::collections::HashMap;
 use std::fmt;
-use std::fs::{create_dir_all, read, read_to_string, DirEntry, File};
+use std::fs::{create_dir_all, read, rename, DirEntry, File};
+use std::hash::{Hash, Hasher};
+use std::io::{BufWriter, Write};
 use std::mem;
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
-use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use std::time::Instant;
@@ -29,22 +32,32 @@ use std::{thread, u8};
 
 use byteorder::{ByteOrder, LittleEndian};
 use chrono::prelude::*;
-use fnv::FnvHashSet;
-use frust5_api::*;
+use fnv::{FnvHashSet, FnvHasher};
+use indicatif::ProgressBar;
 use memmap2::Mmap;
 use ndarray::{s, Array1, ArrayBase, ArrayView1, Dim, ViewRepr};
-use ndarray_npy::{read_npy, ReadNpyError, ViewNpyExt};
+use ndarray_npy::{read_npy, write_npy, ReadNpyError, ViewNpyExt};
 
 use rand::distributions::WeightedIndex;
 use rand::prelude::*;
-use rand_distr::{Distribution, SkewNormal};
-use serde::Deserialize;
+use rand_distr::{Distribution, Exp, Normal, SkewNormal};
+use serde::{Deserialize, Serialize};
 use tonic::{Request, Response, Status};
 use uuid::Uuid;
 
+use crate::barcodes;
 use crate::cli::Cli;
+use crate::clock;
+use crate::library::Library;
+use crate::modifications::{Modification, ReferenceSpan, SampleModifications};
+use crate::output::fast5::Fast5Writer;
+use crate::output::pod5::Pod5Writer;
+use crate::output::slow5::Slow5Writer;
+use crate::output::{picoamps_to_raw, EndReason, FinishedRead, ReadWriter, RunMetadata};
+use crate::pore_model::PoreModel;
+use crate::primer_scheme;
 use crate::r10_simulation as r10_sim;
-use crate::reacquisition_distribution::{ReacquisitionPoisson, SampleDist};
+use crate::reacquisition_distribution::SampleDist;
 use crate::read_length_distribution::ReadLengthDist;
 use crate::services::minknow_api::data::data_service_server::DataService;
 use crate::services::minknow_api::data::get_data_types_response::DataType;
@@ -54,8 +67,35 @@ use crate::services::minknow_api::data::{
     get_live_reads_request, get_live_reads_response, GetDataTypesRequest, GetDataTypesResponse,
     GetLiveReadsRequest, GetLiveReadsResponse,
 };
-use crate::PoreType;
-use crate::{Config, Sample, _load_toml};
+use crate::variants::{Haplotype, Variants};
+use crate::{OutputFormat, PoreType};
+use crate::{Config, Sample, _load_toml, MIN_SHORT_FRAGMENT_BASES};
+
+/// Sample rate of the squiggle shipped in static/ and generated by make_squiggle.py or `icarust squiggle`
+pub const STATIC_SIGNAL_SAMPLE_RATE: f64 = 4000.0;
+/// Most molecules joined together in one chimeric read
+const MAX_CHIMERA_SEGMENTS: usize = 10;
+/// Chunk classification of reads, as given by the analysis configuration service
+const STRAND_CLASSIFICATION: i32 = 83;
+/// Most times to draw a molecule from the library before giving up until later
+const MAX_LIBRARY_DRAWS: usize = 1000;
+/// Seconds an open pore waits to look again if there was nothing in the library to capture
+const LIBRARY_RETRY_SECONDS: f64 = 1.0;
+/// Chunk classification of adapter only events
+const ADAPTER_CLASSIFICATION: i32 = 65;
+/// Mean current and standard deviation in picoamps of a blocked pore
+const BLOCKED_PORE: (f64, f64) = (30.0, 4.0);
+/// Chunk classification of a blocked pore, unavailable
+const BLOCKED_CLASSIFICATION: i32 = 117;
+/// Chunk classification, mean current and standard deviation in picoamps of each kind of noise event
+const NOISE_EVENTS: [(i32, f64, f64); 3] = [
+    // zero - no current flowing through the pore
+    (90, 0.0, 1.5),
+    // multiple - more than one pore in the well, so more current than an open pore
+    (77, 220.0, 12.0),
+    // event - a blip that is not a strand
+    (69, 60.0, 20.0),
+];
 
 /// unused
 #[derive(Debug)]
@@ -68,24 +108,40 @@ struct RunSetup {
 
 /// Stores the view and total length of a squiggle NPY file
 struct FileInfo {
+    /// The contig, amplicon or squiggle file name, for the ground truth of chimeric reads
+    name: String,
+    /// Which haplotype of a sample with variants the contig is
+    haplotype: Option<usize>,
     contig_len: usize,
     view: Option<ArrayBase<ndarray::OwnedRepr<i16>, Dim<[usize; 1]>>>,
     sequence: Option<Vec<i16>>,
+    /// Packed bases, simulated into signal a read at a time. contig_len is in bases rather than samples
+    bases: Option<r10_sim::PackedSequence>,
+    /// Where the contig is on the reference, if it was read from sequence
+    reference: Option<ReferenceSpan>,
 }
 
 impl FileInfo {
     pub fn new(
+        name: String,
         view: Option<ArrayBase<ndarray::OwnedRepr<i16>, Dim<[usize; 1]>>>,
         sequence: Option<Vec<i16>>,
+        bases: Option<r10_sim::PackedSequence>,
     ) -> FileInfo {
-        let array_len = match view {
-            Some(_) => view.as_ref().unwrap().len(),
-            None => sequence.as_ref().unwrap().len(),
+        let array_len = match (&view, &sequence, &bases) {
+            (Some(view), _, _) => view.len(),
+            (None, Some(sequence), _) => sequence.len(),
+            (None, None, Some(bases)) => bases.num_bases(),
+            (None, None, None) => panic!("A contig needs signal or sequence"),
         };
         FileInfo {
+            name,
+            haplotype: None,
             contig_len: array_len,
             view,
             sequence,
+            bases,
+            reference: None,
         }
     }
 }
@@ -94,13 +150,17 @@ impl fmt::Debug for FileInfo {
         write!(
             f,
             "{{\n
+        Name: {}
         Contig_length: {}
         Has Sequence: {}
         Has View: {}
+        Has Bases: {}
         }}",
+            self.name,
             self.contig_len,
             self.view.is_some(),
-            self.sequence.is_some()
+            self.sequence.is_some(),
+            self.bases.is_some()
         )
     }
 }
@@ -115,8 +175,14 @@ struct SampleInfo {
     is_amplicon: bool,
     is_barcoded: bool,
     file_weights: Vec<WeightedIndex<usize>>,
-    /// We use this to determine whether we are reading signal ot Sequence from the file info (R10 -> Sequence)
-    pore_type: PoreType,
+    /// Set if the files are packed bases, to simulate their signal as reads are generated
+    simulator: Option<Arc<r10_sim::Simulator>>,
+    /// Set if the files are amplicons from a primer scheme, which are read end to end unless the read is partial
+    partial_amplicon_chance: Option<f64>,
+    /// Chance another molecule is joined on to a read from this sample
+    chimera_rate: f64,
+    /// Set if bases of the sample are modified, which are chosen for each read as it is simulated
+    modifications: Option<Arc<SampleModifications>>,
 }
 impl fmt::Debug for SampleInfo {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -150,7 +216,6 @@ impl SampleInfo {
         is_amplicon: bool,
         is_barcoded: bool,
         read_len_dist: ReadLengthDist,
-        pore_type: PoreType,
     ) -> SampleInfo {
         SampleInfo {
             name,
@@ -162,7 +227,10 @@ impl SampleInfo {
             is_amplicon,
             is_barcoded,
             file_weights: vec![],
-            pore_type,
+            simulator: None,
+            partial_amplicon_chance: None,
+            chimera_rate: 0.0,
+            modifications: None,
         }
     }
 }
@@ -186,12 +254,14 @@ pub struct DataServiceServicer {
     setup: Arc<Mutex<RunSetup>>,
     break_chunks_ms: u64,
     channel_size: usize,
+    sample_rate: f64,
 }
 
-#[derive(Debug, Deserialize)]
-struct Weights {
-    weights: Vec<usize>,
-    names: Vec<String>,
+/// The contents of a distributions.json, the relative weight of each squiggle file
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Weights {
+    pub weights: Vec<usize>,
+    pub names: Vec<String>,
 }
 
 /// #Internal to the data generation thread
@@ -202,23 +272,420 @@ struct ReadInfo {
     channel: usize,
     stop_receiving: bool,
     read_number: u32,
+    // The read ended before all of its signal was sequenced, at time_unblocked
     was_unblocked: bool,
     write_out: bool,
     start_time: u64,
     start_time_seconds: usize,
     start_time_utc: DateTime<Utc>,
     start_mux: u8,
-    end_reason: u8,
-    channel_number: String,
+    end_reason: EndReason,
     prev_chunk_start: usize,
     duration: usize,
     time_accessed: DateTime<Utc>,
     time_unblocked: DateTime<Utc>,
     dead: bool,
+    // Sample of the read signal the pore blocks at, if it does
+    block_start: Option<usize>,
+    // The block can't be cleared, so the pore dies at the end of the read
+    permanent_block: bool,
+    // Seconds since the start of the experiment the pore was last checked for wearing out
+    last_health_check: u64,
     last_read_len: u64,
     pause: f64,
     // Which sample is this read from - so we can get the chance it kills the pore
     read_sample_name: String,
+    // Where each molecule in the read came from, more than one if the read is chimeric
+    segments: Vec<ReadSegment>,
+    // Chunk classification served with the signal, strand unless this is a junk event
+    classification: i32,
+    // The molecule the pore captures next, chosen when the pore opens
+    next_molecule: Option<Molecule>,
+    // The molecule being read, the first if the read is chimeric
+    molecule: Option<Molecule>,
+}
+
+/// The next molecule an open pore captures, and the kind of event it gives. It is chosen as the pore opens, so the time to
+/// capture it can depend on its length.
+#[derive(Clone, Debug)]
+struct Molecule {
+    sample: String,
+    // Index of the barcode, if the sample is barcoded
+    barcode: Option<usize>,
+    // Length in samples, None for primer scheme amplicons, which are read end to end
+    length: Option<f64>,
+    event: EventKind,
+}
+
+impl Molecule {
+    /// Choose the kind of event, then the sample and barcode of the next molecule by weight, and draw its length. Only
+    /// strands are taken from the library, junk events leave it as it is. Returns the molecule and its length in bases, or
+    /// None if the library has run out.
+    fn choose(
+        samples: &[String],
+        dist: &WeightedIndex<usize>,
+        views: &HashMap<String, SampleInfo>,
+        settings: &ReadSettings,
+        library: &mut Library,
+        rng: &mut StdRng,
+    ) -> Option<(Molecule, f64)> {
+        let event = settings.junk.choose(rng);
+        // pools that are nearly empty are rarely taken from, so give up if nothing is taken after enough tries
+        for _ in 0..MAX_LIBRARY_DRAWS {
+            if library.is_exhausted() {
+                return None;
+            }
+            let sample = &samples[dist.sample(rng)];
+            let sample_info = &views[sample];
+            let barcode = sample_info
+                .barcode_weights
+                .as_ref()
+                .map(|barcode_weights| barcode_weights.sample(rng));
+            if is_strand(event) && !library.try_take(sample, barcode.unwrap_or(0), rng) {
+                continue;
+            }
+            let length = match event {
+                EventKind::ShortFragment => settings.junk.short_fragment_length(rng),
+                _ => sample_info.read_len_dist.sample(rng),
+            };
+            let molecule = Molecule {
+                sample: sample.clone(),
+                barcode,
+                length: (event == EventKind::ShortFragment
+                    || sample_info.partial_amplicon_chance.is_none())
+                .then_some(length),
+                event,
+            };
+            return Some((molecule, length / settings.samples_per_base));
+        }
+        None
+    }
+}
+
+/// Where one molecule of a read came from, for the ground truth of chimeric reads
+#[derive(Clone, Debug)]
+struct ReadSegment {
+    sample: String,
+    contig: String,
+    haplotype: Option<usize>,
+    // Position in the contig's signal (or in bases for packed sequence)
+    contig_start: usize,
+    contig_end: usize,
+    // Sample in the read signal the molecule starts at
+    signal_start: usize,
+    // Bases of the contig that are modified in this read
+    modified: Vec<(usize, Modification)>,
+}
+
+/// One molecule to be put into a read
+struct Segment {
+    signal: Vec<i16>,
+    // Squiggle of the barcodes at either end, if the sample is barcoded
+    barcodes: Option<(Vec<i16>, Vec<i16>)>,
+    // Whether the signal was simulated from sequence, rather than read from a squiggle file
+    simulated: bool,
+    source: ReadSegment,
+}
+
+/// Settings from the simulation profile that are used every time a read is generated
+#[derive(Clone, Debug)]
+struct ReadSettings {
+    sample_rate: f64,
+    samples_per_base: f64,
+    lifetime: PoreLifetime,
+    // scale applied to the signal of each R10 read
+    read_scale: Normal<f64>,
+    // shift in picoamps applied to the signal of each R10 read
+    read_shift: Normal<f64>,
+    structure: ReadStructure,
+    junk: JunkEvents,
+    // direct RNA reads start at the 3' end of the transcript, where the adapter is ligated
+    rna: bool,
+}
+
+impl ReadSettings {
+    pub fn new(config: &Config) -> ReadSettings {
+        let signal = config.get_signal_config();
+        ReadSettings {
+            sample_rate: config.get_sample_rate(),
+            samples_per_base: config.get_samples_per_base(),
+            lifetime: PoreLifetime::new(config),
+            read_scale: Normal::new(1.0, signal.get_read_scale_stdv()).unwrap(),
+            read_shift: Normal::new(0.0, signal.get_read_shift_stdv()).unwrap(),
+            structure: ReadStructure::new(config),
+            junk: JunkEvents::new(config),
+            rna: config.get_rna_kit().is_some(),
+        }
+    }
+}
+
+/// The kinds of event that can be started on a channel
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum EventKind {
+    /// A read of the reference
+    Read,
+    /// Adapter with no strand behind it
+    AdapterOnly,
+    /// A read of the reference too short to map
+    ShortFragment,
+    /// Signal that is not a strand, classified as zero, multiple or event
+    Noise,
+}
+
+/// Whether an event is a strand of the library, so its molecule is taken from the library.
+fn is_strand(event: EventKind) -> bool {
+    matches!(event, EventKind::Read | EventKind::ShortFragment)
+}
+
+/// How often events that aren't reads of the reference happen, and what they look like
+#[derive(Clone, Debug)]
+struct JunkEvents {
+    adapter_only_fraction: f64,
+    short_fragment_fraction: f64,
+    max_short_fragment_bases: usize,
+    noise_fraction: f64,
+    samples_per_base: f64,
+}
+
+impl JunkEvents {
+    pub fn new(config: &Config) -> JunkEvents {
+        let junk_events = config.get_junk_events();
+        JunkEvents {
+            adapter_only_fraction: junk_events.get_adapter_only_fraction(),
+            short_fragment_fraction: junk_events.get_short_fragment_fraction(),
+            max_short_fragment_bases: junk_events.get_max_short_fragment_bases(),
+            noise_fraction: junk_events.get_noise_fraction(),
+            samples_per_base: config.get_samples_per_base(),
+        }
+    }
+
+    /// Choose what kind of event to start next.
+    fn choose(&self, rng: &mut StdRng) -> EventKind {
+        let roll: f64 = rng.gen();
+        if roll < self.adapter_only_fraction {
+            EventKind::AdapterOnly
+        } else if roll < self.adapter_only_fraction + self.short_fragment_fraction {
+            EventKind::ShortFragment
+        } else if roll
+            < self.adapter_only_fraction + self.short_fragment_fraction + self.noise_fraction
+        {
+            EventKind::Noise
+        } else {
+            EventKind::Read
+        }
+    }
+
+    /// Length in samples of a short fragment.
+    fn short_fragment_length(&self, rng: &mut StdRng) -> f64 {
+        rng.gen_range(MIN_SHORT_FRAGMENT_BASES..=self.max_short_fragment_bases) as f64
+            * self.samples_per_base
+    }
+
+    /// Signal of a noise event lasting between 0.1 and 1 seconds, and its chunk classification.
+    fn noise(&self, sample_rate: f64, rng: &mut StdRng) -> (i32, Vec<i16>) {
+        let (classification, mean, stdv) = NOISE_EVENTS[rng.gen_range(0..NOISE_EVENTS.len())];
+        let noise = Normal::new(mean, stdv).unwrap();
+        let samples = (rng.gen_range(0.1..1.0) * sample_rate) as usize;
+        let signal = (0..samples)
+            .map(|_| picoamps_to_raw(noise.sample(rng)))
+            .collect();
+        (classification, signal)
+    }
+}
+
+/// How pores wear out and block
+#[derive(Clone, Debug)]
+struct PoreLifetime {
+    /// Chance the pore blocks part way through a read
+    pore_block_chance: f64,
+    /// Seconds for half of the pores to die, if pores die with time rather than to reach the target yield
+    half_life: Option<f64>,
+    /// Chance each unblock kills the pore
+    unblock_death_chance: f64,
+    /// Samples of blocked signal before MinKNOW clears a block, if a client doesn't unblock it first
+    block_timeout: usize,
+    /// Read length in bases at which a read is as likely as not to block the pore for good
+    permanent_block_bases: Option<f64>,
+    samples_per_base: f64,
+}
+
+impl PoreLifetime {
+    pub fn new(config: &Config) -> PoreLifetime {
+        let pore_lifetime = config.get_pore_lifetime();
+        PoreLifetime {
+            pore_block_chance: pore_lifetime.get_pore_block_chance(),
+            half_life: pore_lifetime.get_half_life_seconds(),
+            unblock_death_chance: pore_lifetime.get_unblock_death_chance(),
+            block_timeout: (pore_lifetime.get_block_timeout_seconds() * config.get_sample_rate())
+                as usize,
+            permanent_block_bases: pore_lifetime.get_permanent_block_bases(),
+            samples_per_base: config.get_samples_per_base(),
+        }
+    }
+
+    /// Chance a pore dies of age over `seconds`, if pores die with time.
+    fn age_death_chance(&self, seconds: u64) -> Option<f64> {
+        self.half_life
+            .map(|half_life| 1.0 - 0.5_f64.powf(seconds as f64 / half_life))
+    }
+
+    /// Whether a read of `samples` signal blocks the pore for good, longer reads being more likely to.
+    fn permanent_block(&self, samples: usize, rng: &mut StdRng) -> bool {
+        self.permanent_block_bases.is_some_and(|bases| {
+            let length = samples as f64 / self.samples_per_base;
+            rng.gen_bool(1.0 - 0.5_f64.powf(length / bases))
+        })
+    }
+
+    /// Signal of a blocked pore, lasting until MinKNOW clears the block.
+    fn blocked_signal(&self, rng: &mut StdRng) -> Vec<i16> {
+        let level = Normal::new(BLOCKED_PORE.0, BLOCKED_PORE.1).unwrap();
+        (0..self.block_timeout)
+            .map(|_| picoamps_to_raw(level.sample(rng)))
+            .collect()
+    }
+}
+
+/// How each read is put together from adapter, stall, barcode and sequence signal
+#[derive(Clone, Debug)]
+struct ReadStructure {
+    /// Adapter signal at the profile's sample rate, loaded once. Empty if neither end of the read has adapter
+    adapter: Vec<i16>,
+    start_adapter: bool,
+    end_adapter: bool,
+    /// Whether reads of barcoded samples end with adapter, which pads the second barcode
+    barcoded_end_adapter: bool,
+    /// Whether there is adapter between the molecules of a chimeric read
+    middle_adapter: bool,
+    /// Number of samples the motor protein stalls for after the start adapter
+    stall: Option<Exp<f64>>,
+    /// Signal of the stall, the level at the end of the adapter
+    stall_level: Normal<f64>,
+    /// Number of samples and signal of the poly(A) tail at the start of direct RNA reads
+    poly_a: Option<(Normal<f64>, Normal<f64>)>,
+    barcode_chance: f64,
+    truncation_chance: f64,
+}
+
+impl ReadStructure {
+    pub fn new(config: &Config) -> ReadStructure {
+        let settings = config.get_read_structure();
+        let start_adapter = settings.get_start_adapter(&config.check_pore_type());
+        let end_adapter = settings.get_end_adapter(&config.check_pore_type(), false);
+        let barcoded_end_adapter = settings.get_end_adapter(&config.check_pore_type(), true);
+        let middle_adapter = settings.get_middle_adapter();
+        // adapter only junk events need the adapter, even if reads don't
+        let adapter_only = config.get_junk_events().get_adapter_only_fraction() > 0.0;
+        let adapter = match start_adapter
+            || end_adapter
+            || barcoded_end_adapter
+            || middle_adapter
+            || adapter_only
+        {
+            true => resample(
+                r10_sim::generate_prefix().expect("NO PREFIX BAD"),
+                STATIC_SIGNAL_SAMPLE_RATE,
+                config.get_sample_rate(),
+            ),
+            false => vec![],
+        };
+        // the stall carries on at the level the adapter finishes on
+        let tail = &adapter[adapter.len().saturating_sub(200)..];
+        let n = tail.len().max(1) as f64;
+        let mean = tail.iter().map(|x| *x as f64).sum::<f64>() / n;
+        let stdv = (tail.iter().map(|x| (*x as f64 - mean).powi(2)).sum::<f64>() / n).sqrt();
+        let stall_samples = settings.get_stall_ms() * config.get_sample_rate() / 1000.0;
+        // the poly(A) tail is a run of the all A kmer
+        let poly_a = config.get_rna_kit().map(|_| {
+            let model = load_pore_model(config);
+            let level = model
+                .level(&"A".repeat(model.k))
+                .expect("Pore model has no level for the poly(A) kmer");
+            let poly_a_samples = settings.get_poly_a_length() * config.get_samples_per_base();
+            let level_noise = config.get_signal_config().get_level_noise();
+            (
+                Normal::new(poly_a_samples, poly_a_samples / 3.0).unwrap(),
+                Normal::new(level.mean, level.stdv * level_noise).unwrap(),
+            )
+        });
+        ReadStructure {
+            adapter,
+            start_adapter,
+            end_adapter,
+            barcoded_end_adapter,
+            middle_adapter,
+            stall: (start_adapter && stall_samples > 0.0)
+                .then(|| Exp::new(1.0 / stall_samples).unwrap()),
+            stall_level: Normal::new(mean, stdv).unwrap(),
+            poly_a,
+            barcode_chance: settings.get_barcode_chance(),
+            truncation_chance: settings.get_truncation_chance(),
+        }
+    }
+
+    /// Add the motor protein stall after the start adapter, if there is one.
+    fn extend_stall(&self, squiggle: &mut Vec<i16>, rng: &mut StdRng) {
+        if let Some(stall) = &self.stall {
+            let stall_len = stall.sample(rng) as usize;
+            squiggle.extend((0..stall_len).map(|_| self.stall_level.sample(rng) as i16));
+        }
+    }
+
+    /// Signal of an adapter that is captured with no strand behind it, the adapter and stall.
+    fn adapter_only(&self, rng: &mut StdRng) -> Vec<i16> {
+        let mut squiggle = self.adapter.clone();
+        self.extend_stall(&mut squiggle, rng);
+        squiggle
+    }
+
+    /// Put a read together as [start adapter + stall] + [poly(A)] + [barcode 1] + read + [barcode 2] + [end adapter].
+    /// Each barcode is only present with the barcode chance, and truncated reads stop part way through the read signal,
+    /// losing everything after it. Whether there is an end adapter depends on whether the last molecule is barcoded. Chimeric reads have more than one segment, each with its own barcodes, and adapter
+    /// between them if the middle adapter is set. Returns the signal and where each segment that made it into the read
+    /// starts.
+    fn assemble(&self, segments: Vec<Segment>, rng: &mut StdRng) -> (Vec<i16>, Vec<ReadSegment>) {
+        let mut squiggle = vec![];
+        let mut read_segments = vec![];
+        let mut barcoded = false;
+        if self.start_adapter {
+            squiggle.extend(&self.adapter);
+            self.extend_stall(&mut squiggle, rng);
+        }
+        for (index, mut segment) in segments.into_iter().enumerate() {
+            if index > 0 && self.middle_adapter {
+                squiggle.extend(&self.adapter);
+            }
+            if let Some((poly_a_samples, poly_a_level)) = &self.poly_a {
+                let poly_a_len = poly_a_samples.sample(rng).max(0.0) as usize;
+                squiggle.extend((0..poly_a_len).map(|_| picoamps_to_raw(poly_a_level.sample(rng))));
+            }
+            barcoded = segment.barcodes.is_some();
+            let (barcode_1, barcode_2) = segment.barcodes.unwrap_or_default();
+            if rng.gen_bool(self.barcode_chance) {
+                squiggle.extend(barcode_1);
+            }
+            segment.source.signal_start = squiggle.len();
+            read_segments.push(segment.source);
+            if rng.gen_bool(self.truncation_chance) && segment.signal.len() > 1 {
+                let truncate_at = rng.gen_range(1..segment.signal.len());
+                segment.signal.truncate(truncate_at);
+                squiggle.extend(segment.signal);
+                return (squiggle, read_segments);
+            }
+            squiggle.extend(segment.signal);
+            if rng.gen_bool(self.barcode_chance) {
+                squiggle.extend(barcode_2);
+            }
+        }
+        let end_adapter = match barcoded {
+            true => self.barcoded_end_adapter,
+            false => self.end_adapter,
+        };
+        if end_adapter {
+            squiggle.extend(&self.adapter);
+        }
+        (squiggle, read_segments)
+    }
 }
 
 impl fmt::Debug for ReadInfo {
@@ -264,21 +731,57 @@ fn convert_to_u8(raw_data: Vec<i16>) -> Vec<u8> {
     dst
 }
 
-/// Create the output dir to write fast5 too, if it doesn't already exist
-fn create_ouput_dir(output_dir: &std::path::PathBuf) -> std::io::Result<()> {
-    create_dir_all(output_dir)?;
-    Ok(())
-}
-
 /// Create a HashMap of barcode name to a tuple of the I16 squiggle of the 1st and 2nd form of the barcode.
-fn create_barcode_squig_hashmap(config: &Config) -> HashMap<String, (Vec<i16>, Vec<i16>)> {
+/// Barcodes with a sequence in `custom_barcodes` or the barcode kit are simulated with the pore model, anything else is read
+/// from the precomputed squiggle in static/barcode_squiggle.
+fn create_barcode_squig_hashmap(
+    config: &Config,
+    simulator: &mut Option<Arc<r10_sim::Simulator>>,
+) -> HashMap<String, (Vec<i16>, Vec<i16>)> {
     let mut barcodes: HashMap<String, (Vec<i16>, Vec<i16>)> = HashMap::new();
+    let kit = config.get_barcode_kit();
+    let mut rng: StdRng = rand::SeedableRng::seed_from_u64(config.get_rand_seed());
     for sample in config.sample.iter() {
         if let Some(barcode_vec) = &sample.barcodes {
             for barcode in barcode_vec.iter() {
-                let (barcode_squig_1, barcode_squig_2) =
-                    get_barcode_squiggle(barcode, config.check_pore_type()).unwrap();
-                barcodes.insert(barcode.clone(), (barcode_squig_1, barcode_squig_2));
+                let sequence = config
+                    .custom_barcodes
+                    .as_ref()
+                    .and_then(|custom| custom.get(barcode).map(String::as_str))
+                    .or_else(|| kit.and_then(|kit| kit.barcode_sequence(barcode)));
+                let squiggle = match (sequence, kit) {
+                    (Some(sequence), _) => {
+                        // custom barcodes without a kit are native barcodes
+                        let (front, rear) = kit
+                            .unwrap_or(&barcodes::NATIVE_24)
+                            .flanked_sequences(sequence);
+                        let simulator = simulator.get_or_insert_with(|| load_simulator(config));
+                        let mut simulate = |sequence: String| {
+                            r10_sim::sequence_to_signal(
+                                &simulator.model,
+                                sequence.as_bytes(),
+                                &simulator.profile,
+                                &mut rng,
+                                &ProgressBar::hidden(),
+                            )
+                        };
+                        (simulate(front), simulate(rear))
+                    }
+                    (None, Some(kit)) => panic!(
+                        "Barcode {barcode} is not built in to {}, add its sequence to custom_barcodes",
+                        kit.names[0]
+                    ),
+                    (None, None) => {
+                        let (barcode_squig_1, barcode_squig_2) =
+                            get_barcode_squiggle(barcode, config.check_pore_type()).unwrap();
+                        let sample_rate = config.get_sample_rate();
+                        (
+                            resample(barcode_squig_1, STATIC_SIGNAL_SAMPLE_RATE, sample_rate),
+                            resample(barcode_squig_2, STATIC_SIGNAL_SAMPLE_RATE, sample_rate),
+                        )
+                    }
+                };
+                barcodes.insert(barcode.clone(), squiggle);
             }
         }
     }
@@ -313,181 +816,256 @@ fn get_barcode_squiggle(
         barcode, "2", r10_suffix
     ))?;
     let barcode_arr_2: Vec<i16> = barcode_arr_2.to_vec();
-    Ok((barcode_arr_1, barcode_arr_2))
+    Ok(match pore_type {
+        PoreType::R10 => (
+            r10_sim::calibrate_static_r10(barcode_arr_1),
+            r10_sim::calibrate_static_r10(barcode_arr_2),
+        ),
+        PoreType::R9 => (barcode_arr_1, barcode_arr_2),
+    })
 }
 
-/// Start the thread that will handle writing out the FAST5 file,
+/// Start the thread that will handle writing out the finished reads, in the output format set in the simulation profile.
+/// Reads are written out once `reads_per_file` are waiting, or every `flush_interval_seconds` if fewer have arrived.
+/// The thread runs until the data generation thread drops its sender, at which point every pending read is written out and the
+/// total number of reads written is sent down `write_out_done`.
 fn start_write_out_thread(
     run_id: String,
     config: Cli,
     output_path: PathBuf,
-    write_out_gracefully: Arc<Mutex<bool>>,
+    write_out_done: SyncSender<usize>,
 ) -> SyncSender<ReadInfo> {
-    let (complete_read_tx, complete_read_rx) = sync_channel(8000);
+    let (complete_read_tx, complete_read_rx) = sync_channel::<ReadInfo>(8000);
     let x = config;
 
     thread::spawn(move || {
-        let mut read_infos: Vec<ReadInfo> = Vec::with_capacity(8000);
-        let exp_start_time = Utc::now();
-        let iso_time = exp_start_time.to_rfc3339_opts(SecondsFormat::Millis, false);
-        let config = _load_toml(&x.simulation_profile);
-        let experiment_duration = config.get_experiment_duration_set().to_string();
+        let exp_start_time = clock::now();
+        let config = _load_toml(x.get_simulation_profile());
+        let reads_per_file = config.get_reads_per_file();
+        let flush_interval = config.get_flush_interval();
+        let sample_rate = config.get_sample_rate();
+        let mut read_infos: Vec<ReadInfo> = Vec::with_capacity(reads_per_file);
+        let metadata = RunMetadata::new(&run_id, &config, exp_start_time);
         // std::env::set_var("HDF5_PLUGIN_PATH", "./vbz_plugin".resolve().as_os_str());
-        let context_tags = HashMap::from([
-            ("barcoding_enabled", "0"),
-            ("experiment_duration_set", experiment_duration.as_str()),
-            ("experiment_type", "genomic_dna"),
-            ("local_basecalling", "0"),
-            ("package", "bream4"),
-            ("package_version", "6.3.5"),
-            ("sample_frequency", "4000"),
-            ("sequencing_kit", "sqk-lsk109"),
-        ]);
-        let tracking_id = HashMap::from([
-            ("asic_id", "817405089"),
-            ("asic_id_eeprom", "5661715"),
-            ("asic_temp", "29.357218"),
-            ("asic_version", "IA02D"),
-            ("auto_update", "0"),
-            (
-                "auto_update_source",
-                "https,//mirror.oxfordnanoportal.com/software/MinKNOW/",
-            ),
-            ("bream_is_standard", "0"),
-            ("configuration_version", "4.4.13"),
-            ("device_id", "Bantersaurus"),
-            ("device_type", config.parameters.position.as_str()),
-            ("distribution_status", "stable"),
-            ("distribution_version", "21.10.8"),
-            (
-                "exp_script_name",
-                "sequencing/sequencing_MIN106_DNA,FLO-MIN106,SQK-LSK109",
-            ),
-            ("exp_script_purpose", "sequencing_run"),
-            ("exp_start_time", iso_time.as_str()),
-            ("flow_cell_id", config.parameters.flowcell_name.as_str()),
-            ("flow_cell_product_code", "FLO-MIN106"),
-            ("guppy_version", "5.0.17+99baa5b"),
-            ("heatsink_temp", "34.066406"),
-            ("host_product_code", "GRD-X5B003"),
-            ("host_product_serial_number", "NOTFOUND"),
-            ("hostname", "master"),
-            ("installation_type", "nc"),
-            ("local_firmware_file", "1"),
-            ("operating_system", "ubuntu 16.04"),
-            (
-                "protocol_group_id",
-                config.parameters.experiment_name.as_str(),
-            ),
-            ("protocol_run_id", "SYNTHETIC_RUN"),
-            ("protocol_start_time", iso_time.as_str()),
-            ("protocols_version", "6.3.5"),
-            ("run_id", run_id.as_str()),
-            ("sample_id", config.parameters.sample_name.as_str()),
-            ("usb_config", "fx3_1.2.4#fpga_1.2.1#bulk#USB300"),
-            ("version", "4.4.3"),
-        ]);
-        let mut read_numbers_seen: FnvHashSet<String> =
-            FnvHashSet::with_capacity_and_hasher(4000, Default::default());
-        let mut file_counter = 0;
-        let output_dir = PathBuf::from(format!("{}/fast5_pass/", output_path.display()));
-        if !output_dir.exists() {
-            create_ouput_dir(&output_dir).unwrap();
-        }
+        let mut writer: Box<dyn ReadWriter> = match config.check_output_format() {
+            OutputFormat::Fast5 => Box::new(Fast5Writer::new(&output_path, metadata).unwrap()),
+            OutputFormat::Pod5 => Box::new(Pod5Writer::new(&output_path, metadata).unwrap()),
+            OutputFormat::Slow5 => {
+                Box::new(Slow5Writer::new(&output_path, metadata, None).unwrap())
+            }
+            OutputFormat::Blow5(compression) => {
+                Box::new(Slow5Writer::new(&output_path, metadata, Some(compression)).unwrap())
+            }
+        };
+        let mut total_written: usize = 0;
+        // ground truth for chimeric reads and reads of haplotypes, only created if there are any
+        let mut read_truth: Option<BufWriter<File>> = None;
+        // ground truth for reads with modified bases
+        let mut modifications: Option<BufWriter<File>> = None;
+        let mut last_flush = Instant::now();
         // loop to collect reads and write out files
         loop {
-            for finished_read_info in complete_read_rx.try_iter() {
-                read_infos.push(finished_read_info);
-            }
-            let z = { *write_out_gracefully.lock().unwrap() };
-
-            // this isn't perfect. if we are finsihing up a run and have more than 4000 reads waiting to be written out, we will lose the excess reads over 4000
-            if read_infos.len() >= 4000 || z {
-                let fast5_file_name = format!(
-                    "{}/{}_pass_{}_{}.fast5",
-                    &output_dir.display(),
-                    config.parameters.flowcell_name,
-                    &run_id[0..6],
-                    file_counter
+            let timeout = flush_interval.saturating_sub(last_flush.elapsed());
+            let finished = match complete_read_rx.recv_timeout(timeout) {
+                Ok(finished_read_info) => {
+                    read_infos.push(finished_read_info);
+                    false
+                }
+                Err(RecvTimeoutError::Timeout) => false,
+                // the data generation thread has stopped, so there are no more reads coming
+                Err(RecvTimeoutError::Disconnected) => true,
+            };
+            while read_infos.len() >= reads_per_file {
+                total_written += write_batch(
+                    writer.as_mut(),
+                    read_infos.drain(..reads_per_file),
+                    sample_rate,
+                    &output_path,
+                    &mut read_truth,
+                    &mut modifications,
                 );
-                // drain 4000 reads and write them into a FAST5 file
-                let mut multi = MultiFast5File::new(fast5_file_name.clone(), OpenMode::Append);
-                info!("Writing out file to {}", fast5_file_name);
-                let range_end = std::cmp::min(4000, read_infos.len());
-                for to_write_info in read_infos.drain(..range_end) {
-                    // skip this read if we are trying to write it out twice
-                    if !read_numbers_seen.insert(to_write_info.read_id.clone()) {
-                        continue;
-                    }
-                    let mut new_end = to_write_info.read.len();
-                    if to_write_info.was_unblocked {
-                        let unblock_time = to_write_info.time_unblocked;
-                        let prev_time = to_write_info.start_time_utc;
-                        let elapsed_time = unblock_time.time() - prev_time.time();
-                        let stop = convert_milliseconds_to_samples(elapsed_time.num_milliseconds());
-                        new_end = min(stop, to_write_info.read.len());
-                    }
-                    let signal = to_write_info.read[0..new_end].to_vec();
-                    debug!("{to_write_info:#?}");
-                    if signal.is_empty() {
-                        error!("Attempt to write empty signal");
-                        continue;
-                    };
-                    let raw_attrs: HashMap<&str, RawAttrsOpts> = HashMap::from([
-                        ("duration", RawAttrsOpts::Duration(signal.len() as u32)),
-                        (
-                            "end_reason",
-                            RawAttrsOpts::EndReason(to_write_info.end_reason),
-                        ),
-                        ("median_before", RawAttrsOpts::MedianBefore(100.0)),
-                        (
-                            "read_id",
-                            RawAttrsOpts::ReadId(to_write_info.read_id.as_str()),
-                        ),
-                        (
-                            "read_number",
-                            RawAttrsOpts::ReadNumber(to_write_info.read_number as i32),
-                        ),
-                        ("start_mux", RawAttrsOpts::StartMux(to_write_info.start_mux)),
-                        (
-                            "start_time",
-                            RawAttrsOpts::StartTime(to_write_info.start_time),
-                        ),
-                    ]);
-                    let channel_info = ChannelInfo::new(
-                        8192_f64,
-                        6.0,
-                        1500.0,
-                        4000.0,
-                        to_write_info.channel_number.clone(),
+                last_flush = Instant::now();
+            }
+            if finished || last_flush.elapsed() >= flush_interval {
+                if !read_infos.is_empty() {
+                    total_written += write_batch(
+                        writer.as_mut(),
+                        read_infos.drain(..),
+                        sample_rate,
+                        &output_path,
+                        &mut read_truth,
+                        &mut modifications,
                     );
-                    multi
-                        .create_empty_read(
-                            to_write_info.read_id.clone(),
-                            run_id.clone(),
-                            &tracking_id,
-                            &context_tags,
-                            channel_info,
-                            &raw_attrs,
-                            signal,
-                        )
-                        .unwrap();
                 }
-                file_counter += 1;
-                read_numbers_seen.clear();
+                last_flush = Instant::now();
             }
-            {
-                if *write_out_gracefully.lock().unwrap() {
-                    break;
-                }
+            if finished {
+                break;
             }
-            thread::sleep(Duration::from_millis(1));
         }
-        info!("exiting write out thread");
+        info!("exiting write out thread, wrote out {total_written} reads in total");
+        // nobody may be waiting on this if we weren't stopped by Ctrl+C, so it's fine if it fails
+        let _ = write_out_done.send(total_written);
     });
     complete_read_tx
 }
 
+/// Write where each molecule of a read came from to read_truth.tsv in the output directory, creating it on the first read
+/// that needs it. Contig positions are in samples of the contig's signal, or bases if the signal is simulated on demand.
+/// Haplotypes are numbered from 1, as the alleles of a genotype are ordered, or "." if the sample has no variants.
+fn write_read_truth(
+    read_truth: &mut Option<BufWriter<File>>,
+    output_path: &Path,
+    read_id: &str,
+    segments: &[ReadSegment],
+) {
+    if read_truth.is_none() {
+        let mut file = BufWriter::new(
+            File::create(output_path.join("read_truth.tsv"))
+                .expect("Failed to create read_truth.tsv"),
+        );
+        writeln!(
+            file,
+            "read_id\tsegment\tsample\tcontig\thaplotype\tcontig_start\tcontig_end\tsignal_start"
+        )
+        .unwrap();
+        *read_truth = Some(file);
+    }
+    let file = read_truth.as_mut().unwrap();
+    for (index, segment) in segments.iter().enumerate() {
+        let haplotype = segment
+            .haplotype
+            .map_or(String::from("."), |haplotype| (haplotype + 1).to_string());
+        writeln!(
+            file,
+            "{read_id}\t{index}\t{}\t{}\t{haplotype}\t{}\t{}\t{}",
+            segment.sample,
+            segment.contig,
+            segment.contig_start,
+            segment.contig_end,
+            segment.signal_start
+        )
+        .unwrap();
+    }
+    file.flush().unwrap();
+}
+
+/// Write the modified bases of each molecule of a read to modifications.tsv in the output directory, creating it on the
+/// first read with a modified base. One line per modified base, with its position in the contig in bases.
+fn write_modifications(
+    modifications: &mut Option<BufWriter<File>>,
+    output_path: &Path,
+    read_id: &str,
+    segments: &[ReadSegment],
+) {
+    if modifications.is_none() {
+        let mut file = BufWriter::new(
+            File::create(output_path.join("modifications.tsv"))
+                .expect("Failed to create modifications.tsv"),
+        );
+        writeln!(file, "read_id\tsegment\tcontig\tposition\tmodification").unwrap();
+        *modifications = Some(file);
+    }
+    let file = modifications.as_mut().unwrap();
+    for (index, segment) in segments.iter().enumerate() {
+        for (position, modification) in &segment.modified {
+            writeln!(
+                file,
+                "{read_id}\t{index}\t{}\t{position}\t{}",
+                segment.contig,
+                modification.code()
+            )
+            .unwrap();
+        }
+    }
+    file.flush().unwrap();
+}
+
+/// Write a batch of reads into a single file, returning the number of reads that were written. The ground truth of each
+/// chimeric read, or read of a haplotype, is written to read_truth.tsv and its modified bases to modifications.tsv, if the
+/// read is written out.
+fn write_batch(
+    writer: &mut dyn ReadWriter,
+    batch: impl Iterator<Item = ReadInfo>,
+    sample_rate: f64,
+    output_path: &Path,
+    read_truth: &mut Option<BufWriter<File>>,
+    modifications: &mut Option<BufWriter<File>>,
+) -> usize {
+    let mut read_ids_seen: FnvHashSet<String> = FnvHashSet::default();
+    let mut to_write = Vec::with_capacity(batch.size_hint().0);
+    for mut to_write_info in batch {
+        // skip this read if we are trying to write it out twice
+        if !read_ids_seen.insert(to_write_info.read_id.clone()) {
+            continue;
+        }
+        debug!("{to_write_info:#?}");
+        let segments = mem::take(&mut to_write_info.segments);
+        match finish_read(to_write_info, sample_rate) {
+            Some(finished_read) => {
+                if segments.len() > 1 || segments.iter().any(|segment| segment.haplotype.is_some())
+                {
+                    write_read_truth(read_truth, output_path, &finished_read.read_id, &segments);
+                }
+                if segments.iter().any(|segment| !segment.modified.is_empty()) {
+                    write_modifications(
+                        modifications,
+                        output_path,
+                        &finished_read.read_id,
+                        &segments,
+                    );
+                }
+                to_write.push(finished_read)
+            }
+            None => error!("Attempt to write empty signal"),
+        }
+    }
+    if to_write.is_empty() {
+        return 0;
+    }
+    match writer.write_reads(&to_write) {
+        Ok(file_name) => {
+            info!(
+                "Written out {} reads to {}",
+                to_write.len(),
+                file_name.display()
+            );
+            to_write.len()
+        }
+        Err(err) => {
+            error!("Failed to write out {} reads - {err}", to_write.len());
+            0
+        }
+    }
+}
+
+/// Convert a ReadInfo into the read that is written out, truncating the signal to the point it was unblocked at.
+/// Returns None if there is no signal to write out.
+fn finish_read(read_info: ReadInfo, sample_rate: f64) -> Option<FinishedRead> {
+    let mut new_end = read_info.read.len();
+    if read_info.was_unblocked {
+        let unblock_time = read_info.time_unblocked;
+        let prev_time = read_info.start_time_utc;
+        let elapsed_time = unblock_time - prev_time;
+        let stop = convert_milliseconds_to_samples(elapsed_time.num_milliseconds(), sample_rate);
+        new_end = min(stop, read_info.read.len());
+    }
+    let mut signal = read_info.read;
+    signal.truncate(new_end);
+    if signal.is_empty() {
+        return None;
+    }
+    Some(FinishedRead {
+        read_id: read_info.read_id,
+        read_number: read_info.read_number,
+        channel: read_info.channel,
+        start_mux: read_info.start_mux,
+        start_time: read_info.start_time,
+        end_reason: read_info.end_reason,
+        signal,
+    })
+}
+
 fn start_unblock_thread(
     channel_read_info: Arc<Mutex<Vec<ReadInfo>>>,
     run_setup: Arc<Mutex<RunSetup>>,
@@ -624,13 +1202,8 @@ fn unblock_reads(
         // if we are dealing with a new read, set the new read num as the last dealt with read num ath this channel number
         channel_num_to_read_num[channel_number] = read_num;
     };
-    // set the was unblocked field for writing out
-    value.was_unblocked = true;
-    value.write_out = true;
-    // set the time unblocked so we can work out the length of the read to serve
-    value.time_unblocked = Utc::now();
-    // end reason of unblock
-    value.end_reason = 4;
+    // set the was unblocked field for writing out, and the time unblocked so we can work out the length of the read to serve
+    end_read_early(value, EndReason::DataServiceUnblockMuxChange);
     (
         Some(get_live_reads_response::ActionResponse {
             action_id,
@@ -641,6 +1214,24 @@ fn unblock_reads(
     )
 }
 
+/// Put the molecule a pore is waiting to capture back into the library, as it will never be read.
+fn put_back_next_molecule(value: &mut ReadInfo, library: &mut Library) {
+    if let Some(molecule) = value.next_molecule.take() {
+        if is_strand(molecule.event) {
+            library.put_back(&molecule.sample, molecule.barcode.unwrap_or(0));
+        }
+    }
+}
+
+/// End a read that is being sequenced now, rather than when all of its signal has been served.
+/// The read is truncated to the current time when it is written out.
+fn end_read_early(value: &mut ReadInfo, end_reason: EndReason) {
+    value.was_unblocked = true;
+    value.write_out = true;
+    value.time_unblocked = clock::now();
+    value.end_reason = end_reason;
+}
+
 /// Stop sending read data, sets Stop receiving to True.
 fn stop_sending_read(
     _action: get_live_reads_request::StopFurtherData,
@@ -711,9 +1302,10 @@ impl<P: AsRef<Path>> FileExtension for P {
 }
 
 /// Read the config file and parse the sample fields. This then returns any necessary Sample infos, setup
-/// according to the structure of the run. This structure changes based on whether the sample is barcoded, amplicons based and has provided weights.
+/// according to the structure of the run. The simulator is reused if the barcodes already needed it. This structure changes based on whether the sample is barcoded, amplicons based and has provided weights.
 fn process_samples_from_config(
     config: &Config,
+    mut simulator: Option<Arc<r10_sim::Simulator>>,
 ) -> (HashMap<String, SampleInfo>, WeightedIndex<usize>) {
     // a hashamp keyed from sample into the information about this sample that we need. The Sampleinfo value is created in the function generate_amplicon_sampling_distribution
     // and is then mutated by accessing the hashmap.
@@ -722,16 +1314,8 @@ fn process_samples_from_config(
     let sample_weights = read_sample_distribution(config);
     // Seeded rng for generated weighted dists
     let mut rng: rand::rngs::StdRng = rand::SeedableRng::seed_from_u64(config.get_rand_seed());
-    let kmer_string =
-        read_to_string("static/R10_model.tsv").expect("Failed to read kmers to string");
-    let kmers = match config.check_pore_type() {
-        PoreType::R10 => {
-            let (_, kmer_hashmap) =
-                r10_sim::parse_kmers(&kmer_string).expect("Failed to parse R10 kmers");
-            Some(kmer_hashmap)
-        }
-        PoreType::R9 => None,
-    };
+    let samples_per_base = config.get_samples_per_base();
+    let on_demand = config.get_signal_config().get_on_demand();
     // Now iterate all the samples and setup any required fields for the type of run wie have. Possible combos:
     //      Amplicon barcoded
     //      Amplicon unbarcoded
@@ -747,6 +1331,7 @@ fn process_samples_from_config(
                 .map(|x| x.unwrap())
                 .collect();
             t.sort_by_key(|a| a.path());
+            let mut record_names = vec![];
             for entry in t {
                 // only read files that are .npy squiggle
                 if entry.path().extension().unwrap().to_str().unwrap() == "npy" {
@@ -755,19 +1340,25 @@ fn process_samples_from_config(
                         &mut views,
                         &entry.path().clone(),
                         config.global_mean_read_length,
+                        samples_per_base,
                         sample,
                     );
                 } else if entry.path().is_fasta() {
                     info!("Reading view of sequence for {:#?}", entry.path());
-                    read_views_of_sequence_data(
+                    record_names.extend(read_views_of_sequence_data(
                         &mut views,
-                        &sample.input_genome.clone(),
+                        &entry.path(),
                         config.global_mean_read_length,
+                        samples_per_base,
                         sample,
-                        kmers.as_ref().unwrap(),
-                    );
+                        simulator.get_or_insert_with(|| load_simulator(config)),
+                        on_demand,
+                        config.signal_cache.as_deref(),
+                        &mut rng,
+                    ));
                 }
             }
+            check_amplicons_matched(sample, &views, &record_names);
             // if the sample is an amplicon based sample we want to get the relative distributions
             let sample_info = views.get_mut(&sample.name).unwrap();
 
@@ -775,10 +1366,8 @@ fn process_samples_from_config(
                 Some(_) => read_sample_distribution_files(sample),
                 // generate amplicon distributions for each barcode
                 None => {
-                    let num_contigs = match config.check_pore_type() {
-                        PoreType::R10 => r10_sim::num_sequences(sample.input_genome.clone()),
-                        PoreType::R9 => sample_info.files.len(),
-                    };
+                    // each squiggle file or FASTA record is one contig
+                    let num_contigs = sample_info.files.len();
                     let mut file_distributions = vec![];
                     // If we have barcodes
                     if let Some(barcodes) = &sample.barcodes {
@@ -810,36 +1399,59 @@ fn process_samples_from_config(
         } else {
             debug!("{:#?}", sample);
             if sample.input_genome.is_fasta() {
-                read_views_of_sequence_data(
+                let record_names = read_views_of_sequence_data(
                     &mut views,
                     &sample.input_genome.clone(),
                     config.global_mean_read_length,
+                    samples_per_base,
                     sample,
-                    kmers.as_ref().unwrap(),
+                    simulator.get_or_insert_with(|| load_simulator(config)),
+                    on_demand,
+                    config.signal_cache.as_deref(),
+                    &mut rng,
                 );
+                check_amplicons_matched(sample, &views, &record_names);
             } else {
                 read_views_of_squiggle_data(
                     &mut views,
                     &sample.input_genome.clone(),
                     config.global_mean_read_length,
+                    samples_per_base,
                     sample,
                 );
             }
             let sample_info = views.get_mut(&sample.name).unwrap();
-            match config.check_pore_type() {
-                PoreType::R9 => {
+            match sample.input_genome.is_fasta() {
+                false => {
                     // we will still "sample" randomly from files but will only add 1 - resulting in 0 always being sampled - as the possible sample to be drawn
                     // so we only ever see this file when we generate a read
                     sample_info.file_weights = vec![WeightedIndex::new(&vec![1]).unwrap()];
                 }
-                PoreType::R10 => {
+                true => {
                     let distributions: Vec<WeightedIndex<usize>> = match &sample.weights_files {
                         Some(_) => read_sample_distribution_files(sample),
+                        // uneven coverage of the amplicons of a primer scheme, with dropouts, for each barcode
+                        None if sample.scheme_bed.is_some() => {
+                            let num_barcodes = sample.barcodes.as_ref().map_or(1, |x| x.len());
+                            (0..num_barcodes)
+                                .map(|_| {
+                                    generate_amplicon_sampling_distribution(
+                                        sample_info.files.len(),
+                                        &mut rng,
+                                        sample.get_amplicon_dropout(),
+                                    )
+                                })
+                                .collect()
+                        }
                         // generate amplicon distributions for each barcode
                         None => {
-                            let num_contigs = r10_sim::num_sequences(sample.input_genome.clone());
-                            let read_lengths =
-                                r10_sim::sequence_lengths(sample.input_genome.clone());
+                            // weight each record, haplotype and region by its length
+                            let num_contigs = sample_info.files.len();
+                            let read_lengths: Vec<usize> = sample_info
+                                .files
+                                .iter()
+                                .map(|file_info| file_info.contig_len)
+                                .collect();
                             let mut file_distributions = vec![];
                             // If we have barcodes
                             if let Some(barcodes) = &sample.barcodes {
@@ -914,6 +1526,27 @@ fn generate_file_sampling_distribution(
     WeightedIndex::new(&distribution).unwrap()
 }
 
+/// Generate the weighted index for the amplicons of a primer scheme. Coverage is uneven in the same way as
+/// generate_file_sampling_distribution, and each amplicon drops out with the given chance, so it is never read.
+fn generate_amplicon_sampling_distribution(
+    num_amplicons: usize,
+    randay: &mut rand::rngs::StdRng,
+    dropout_chance: f64,
+) -> WeightedIndex<usize> {
+    let skew_normal: SkewNormal<f64> = SkewNormal::new(7.0, 2.0, 1.0).unwrap();
+    let mut distribution: Vec<usize> = (0..num_amplicons)
+        .map(|_| match randay.gen_bool(dropout_chance) {
+            true => 0,
+            false => skew_normal.sample(randay).ceil().max(1.0) as usize,
+        })
+        .collect();
+    // something has to amplify
+    if distribution.iter().all(|weight| *weight == 0) {
+        distribution[randay.gen_range(0..num_amplicons)] = 1;
+    }
+    WeightedIndex::new(&distribution).unwrap()
+}
+
 /// Generate the weighted index for all the barcodes on a sample
 fn generate_barcode_weights(
     barcode_weights: Option<&Vec<usize>>,
@@ -933,6 +1566,35 @@ fn generate_barcode_weights(
     }
 }
 
+/// Create the library of molecules left to sequence, with a pool for each barcode of each sample that has a number of
+/// molecules set.
+fn build_library(config: &Config) -> Library {
+    let mut library = Library::new();
+    for sample in &config.sample {
+        let Some(molecules) = sample.molecules else {
+            library.add_unlimited();
+            continue;
+        };
+        let return_chance = sample.get_rejected_return_chance();
+        match &sample.barcodes {
+            Some(barcodes) => {
+                for (index, barcode) in barcodes.iter().enumerate() {
+                    let label = format!("{}/{barcode}", sample.name);
+                    library.add_pool(&sample.name, index, label, molecules, return_chance);
+                }
+            }
+            None => library.add_pool(
+                &sample.name,
+                0,
+                sample.name.clone(),
+                molecules,
+                return_chance,
+            ),
+        }
+    }
+    library
+}
+
 /// Iterate the samples in the config toml and load the weights in
 fn read_sample_distribution(config: &Config) -> WeightedIndex<usize> {
     let mut weights: Vec<usize> = Vec::with_capacity(config.sample.len());
@@ -942,53 +1604,163 @@ fn read_sample_distribution(config: &Config) -> WeightedIndex<usize> {
     WeightedIndex::new(&weights).unwrap()
 }
 
-/// Mutably creates the views into the sequence for a given sample
+/// Panic if a sample has a primer scheme but none of its amplicons are on the FASTA records that were read, naming the
+/// chroms of the BED and the ids of the FASTA so the mismatch can be fixed.
+fn check_amplicons_matched(
+    sample: &Sample,
+    views: &HashMap<String, SampleInfo>,
+    record_names: &[String],
+) {
+    let Some(bed) = &sample.scheme_bed else {
+        return;
+    };
+    if views
+        .get(&sample.name)
+        .is_some_and(|sample_info| !sample_info.files.is_empty())
+    {
+        return;
+    }
+    let mut chroms: Vec<String> = primer_scheme::collapse_amplicon_start_ends(bed)
+        .unwrap_or_else(|err| panic!("Failed to read scheme_bed {} - {err}", bed.display()))
+        .into_iter()
+        .map(|amplicon| amplicon.chrom)
+        .collect();
+    chroms.sort();
+    chroms.dedup();
+    panic!(
+        "None of the amplicons in scheme_bed {} are on the FASTA records of sample {}. The BED has chroms {} but the FASTA has records {}",
+        bed.display(),
+        sample.name,
+        chroms.join(", "),
+        record_names.join(", ")
+    );
+}
+
+/// Mutably creates the views into the sequence for a given sample, returning the names of the FASTA records read.
 ///
 ///
 fn read_views_of_sequence_data(
     views: &mut HashMap<String, SampleInfo>,
     file_path: &std::path::PathBuf,
     global_mean_read_length: Option<f64>,
+    samples_per_base: f64,
     sample_info: &Sample,
-    kmers: &HashMap<String, f64, std::hash::BuildHasherDefault<fnv::FnvHasher>>,
-) {
+    simulator: &Arc<r10_sim::Simulator>,
+    on_demand: bool,
+    cache_dir: Option<&Path>,
+    rng: &mut StdRng,
+) -> Vec<String> {
     info!(
         "Reading sequence information for {:#?} for sample {:#?} MAY TAKE SOME TIME",
         file_path.file_name(),
         sample_info
     );
-    // lazy but cba to pass through
-    let profile = r10_sim::get_sim_profile(r10_sim::SimType::R10);
     let num_seq = r10_sim::num_sequences(file_path);
     info!("Simulating for {num_seq} sequences");
     let mut reader: Box<dyn FastxReader> =
         parse_fastx_file(file_path).expect("Can't find FASTA file at {file_path}");
+    // a primer scheme splits each sequence into its amplicons
+    let amplicons = sample_info.scheme_bed.as_ref().map(|bed| {
+        primer_scheme::collapse_amplicon_start_ends(bed)
+            .unwrap_or_else(|err| panic!("Failed to read scheme_bed {} - {err}", bed.display()))
+    });
+    let variants = sample_info.variants.as_ref().map(|vcf| {
+        Variants::from_file(vcf)
+            .unwrap_or_else(|err| panic!("Failed to read variants {} - {err}", vcf.display()))
+    });
+    let modifications = sample_info.has_modifications().then(|| {
+        let modifications = SampleModifications::new(
+            sample_info.get_motif_modifications(),
+            sample_info.bedmethyl.as_deref(),
+        );
+        Arc::new(modifications.unwrap_or_else(|err| panic!("Failed to read bedmethyl - {err}")))
+    });
+    // each read of a modified sample is modified differently, and each read of a noisy profile has its own noise, so they
+    // have to be simulated as they are generated rather than replaying the same signal for every read of a region
+    let on_demand = on_demand || modifications.is_some() || simulator.profile.is_random();
     let now = Instant::now();
     let mut done = 0;
+    let mut record_names = vec![];
     while let Some(record) = reader.next() {
         let per_record_now = Instant::now();
         let fasta_record = record.unwrap();
-        info!(
-            "Converting {}",
-            String::from_utf8(fasta_record.id().to_vec()).unwrap()
-        );
-        let read_length_dist = sample_info.get_read_len_dist(global_mean_read_length);
-        let file_info = FileInfo::new(
-            None,
-            Some(r10_sim::convert_to_signal(kmers, &fasta_record, &profile).unwrap()),
-        );
-        let sample = views
-            .entry(sample_info.name.clone())
-            .or_insert(SampleInfo::new(
+        let id = String::from_utf8(fasta_record.id().to_vec()).unwrap();
+        info!("Converting {}", id);
+        let seq = fasta_record.seq();
+        let name = id.split_whitespace().next().unwrap_or_default();
+        record_names.push(name.to_string());
+        let regions: Vec<(String, usize, usize)> = match &amplicons {
+            Some(amplicons) => amplicons
+                .iter()
+                .filter(|amplicon| amplicon.chrom == name)
+                .map(|amplicon| {
+                    let amplicon_name = format!("{name}_{}", amplicon.name);
+                    (amplicon_name, amplicon.start, amplicon.end)
+                })
+                .collect(),
+            None => vec![(name.to_string(), 0, seq.len())],
+        };
+        if amplicons.is_some() {
+            info!("{} amplicons on {id}", regions.len());
+        }
+        let sample = views.entry(sample_info.name.clone()).or_insert_with(|| {
+            SampleInfo::new(
                 sample_info.name.clone(),
                 sample_info.barcodes.clone(),
                 sample_info.uneven,
                 sample_info.is_amplicon(),
                 sample_info.is_barcoded(),
-                read_length_dist,
-                PoreType::R10,
-            ));
-        sample.files.push(file_info);
+                sample_info.get_read_len_dist(global_mean_read_length, samples_per_base),
+            )
+        });
+        if on_demand {
+            sample.simulator = Some(Arc::clone(simulator));
+        }
+        sample.modifications = modifications.clone();
+        if amplicons.is_some() {
+            sample.partial_amplicon_chance = Some(sample_info.get_partial_amplicon_chance());
+        }
+        sample.chimera_rate = sample_info.get_chimera_rate();
+        // the variants of each haplotype are applied before the sequence is simulated
+        let haplotypes = match &variants {
+            Some(variants) => variants.haplotypes(name, &seq),
+            None => vec![Haplotype::reference(&seq)],
+        };
+        for (haplotype_index, haplotype) in haplotypes.iter().enumerate() {
+            let hap_seq = &haplotype.sequence;
+            for (region_name, start, end) in &regions {
+                let (start, end) = (haplotype.lift(*start), haplotype.lift(*end));
+                let region = &hap_seq[start.min(hap_seq.len())..end.min(hap_seq.len())];
+                // direct RNA is read from the 3' end
+                let region: Cow<[u8]> = match simulator.reverse {
+                    true => Cow::Owned(region.iter().rev().copied().collect()),
+                    false => Cow::Borrowed(region),
+                };
+                let mut file_info = match on_demand {
+                    true => FileInfo::new(
+                        region_name.clone(),
+                        None,
+                        None,
+                        Some(r10_sim::PackedSequence::new(&region, rng)),
+                    ),
+                    false => FileInfo::new(
+                        region_name.clone(),
+                        None,
+                        Some(convert_sequence_to_signal(&region, simulator, cache_dir)),
+                        None,
+                    ),
+                };
+                file_info.haplotype = variants.is_some().then_some(haplotype_index);
+                file_info.reference = Some(ReferenceSpan {
+                    chrom: name.to_string(),
+                    offset: start,
+                    len: region.len(),
+                    shifts: haplotype.shifts.clone(),
+                    reverse: simulator.reverse,
+                });
+                sample.files.push(file_info);
+            }
+        }
         done += 1;
         info!(
             "Finished converting {done} of {num_seq} in {} seconds",
@@ -997,6 +1769,97 @@ fn read_views_of_sequence_data(
     }
     let _end = now.elapsed().as_secs_f64();
     info!("Read reference into squiggle in {} seconds", _end);
+    record_names
+}
+
+/// Load the pore model set in the simulation profile, or the default model for the pore type.
+fn load_pore_model(config: &Config) -> PoreModel {
+    let path = config.get_pore_model_path().unwrap_or_else(|| {
+        panic!("A pore_model must be set in the simulation profile to simulate R9 signal from sequence.")
+    });
+    info!("Loading pore model from {}", path.display());
+    PoreModel::from_file(&path).unwrap_or_else(|err| panic!("Failed to load pore model - {err}"))
+}
+
+/// Load the pore model and build the profile that signal is simulated from sequence with.
+fn load_simulator(config: &Config) -> Arc<r10_sim::Simulator> {
+    let model = load_pore_model(config);
+    let modified_model = config.get_modified_pore_model_path().map(|path| {
+        let modified_model = PoreModel::from_file(path)
+            .unwrap_or_else(|err| panic!("Failed to load modified pore model - {err}"));
+        if modified_model.k != model.k {
+            panic!("The modified pore model and pore model must have the same k");
+        }
+        modified_model
+    });
+    let dwell_distribution = config.check_dwell_distribution();
+    let level_noise = config.get_signal_config().get_level_noise();
+    // the floats aren't Hash, so hash how they print instead
+    let mut hasher = FnvHasher::default();
+    format!(
+        "{} {} {:?} {} {}",
+        model.digest(),
+        config.get_samples_per_base(),
+        dwell_distribution,
+        level_noise,
+        config.get_rand_seed()
+    )
+    .hash(&mut hasher);
+    Arc::new(r10_sim::Simulator {
+        model,
+        profile: r10_sim::get_sim_profile(
+            r10_sim::SimType::R10,
+            config.get_samples_per_base(),
+            dwell_distribution,
+            level_noise,
+        ),
+        fingerprint: hasher.finish(),
+        reverse: config.get_rna_kit().is_some(),
+        modified_model,
+    })
+}
+
+/// Simulate the signal for a sequence, or load it from the signal cache if it was simulated on a previous start with
+/// the same pore model and settings. Each sequence gets its own rng seeded from the cache key, so cached signal is the
+/// same as freshly simulated signal.
+fn convert_sequence_to_signal(
+    sequence: &[u8],
+    simulator: &r10_sim::Simulator,
+    cache_dir: Option<&Path>,
+) -> Vec<i16> {
+    let mut hasher = FnvHasher::default();
+    simulator.fingerprint.hash(&mut hasher);
+    sequence.hash(&mut hasher);
+    let key = hasher.finish();
+    let cache_path = cache_dir.map(|dir| dir.join(format!("{key:016x}.npy")));
+    if let Some(path) = cache_path.as_ref().filter(|path| path.exists()) {
+        let cached: Result<Array1<i16>, ReadNpyError> = read_npy(path);
+        match cached {
+            Ok(signal) => {
+                info!("Loaded cached signal from {}", path.display());
+                return signal.to_vec();
+            }
+            Err(err) => warn!("Ignoring cached signal {} - {err}", path.display()),
+        }
+    }
+    let mut rng: StdRng = SeedableRng::seed_from_u64(key);
+    let signal =
+        r10_sim::convert_to_signal(&simulator.model, sequence, &simulator.profile, &mut rng)
+            .unwrap();
+    if let Some(path) = cache_path {
+        // write to a temporary file first, so an interrupted write never leaves a truncated array in the cache
+        let tmp_path = path.with_extension("npy.tmp");
+        let written = create_dir_all(path.parent().unwrap())
+            .map_err(|err| err.to_string())
+            .and_then(|_| {
+                write_npy(&tmp_path, &ArrayView1::from(&signal)).map_err(|err| err.to_string())
+            })
+            .and_then(|_| rename(&tmp_path, &path).map_err(|err| err.to_string()));
+        if let Err(err) = written {
+            warn!("Failed to cache signal to {} - {err}", path.display());
+        }
+    }
+    signal
 }
 
 /// Creates Memory mapped views of the precalculated numpy arrays of squiggle for reference genomes, generated by make_squiggle.py
@@ -1007,6 +1870,7 @@ fn read_views_of_squiggle_data(
     views: &mut HashMap<String, SampleInfo>,
     file_info: &std::path::PathBuf,
     global_mean_read_length: Option<f64>,
+    samples_per_base: f64,
     sample_info: &Sample,
 ) {
     info!(
@@ -1019,26 +1883,42 @@ fn read_views_of_squiggle_data(
     let view: ArrayBase<ViewRepr<&i16>, Dim<[usize; 1]>> =
         ArrayView1::<i16>::view_npy(&mmap).unwrap();
     let size = view.shape()[0];
-    let read_length_dist = sample_info.get_read_len_dist(global_mean_read_length);
-    let file_info = FileInfo::new(Some(view.to_owned()), None);
-    let sample = views
-        .entry(sample_info.name.clone())
-        .or_insert(SampleInfo::new(
+    let name = file_info
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().replace(".squiggle", ""))
+        .unwrap_or_default();
+    let file_info = FileInfo::new(name, Some(view.to_owned()), None, None);
+    let sample = views.entry(sample_info.name.clone()).or_insert_with(|| {
+        SampleInfo::new(
             sample_info.name.clone(),
             sample_info.barcodes.clone(),
             sample_info.uneven,
             sample_info.is_amplicon(),
             sample_info.is_barcoded(),
-            read_length_dist,
-            PoreType::R9,
-        ));
+            sample_info.get_read_len_dist(global_mean_read_length, samples_per_base),
+        )
+    });
+    sample.chimera_rate = sample_info.get_chimera_rate();
     sample.files.push(file_info)
 }
 
 /// Convert an elapased period of time in milliseconds tinto samples
 
-fn convert_milliseconds_to_samples(milliseconds: i64) -> usize {
-    (milliseconds * 4) as usize
+fn convert_milliseconds_to_samples(milliseconds: i64, sample_rate: f64) -> usize {
+    (milliseconds as f64 * sample_rate / 1000.0) as usize
+}
+
+/// Resample signal recorded at one sample rate to another, by repeating or dropping samples.
+/// Used for the squiggle in static/ and from make_squiggle.py, which is always at STATIC_SIGNAL_SAMPLE_RATE.
+fn resample(signal: Vec<i16>, from_sample_rate: f64, to_sample_rate: f64) -> Vec<i16> {
+    if signal.is_empty() || (from_sample_rate - to_sample_rate).abs() < f64::EPSILON {
+        return signal;
+    }
+    let step = from_sample_rate / to_sample_rate;
+    let new_len = (signal.len() as f64 / step).round() as usize;
+    (0..new_len)
+        .map(|i| signal[min((i as f64 * step) as usize, signal.len() - 1)])
+        .collect()
 }
 
 ///
@@ -1073,18 +1953,24 @@ fn setup_channel_vec(
             write_out: false,
             start_time: 0,
             start_time_seconds: 0,
-            start_time_utc: Utc::now(),
-            channel_number: channel_number.to_string(),
-            end_reason: 0,
+            start_time_utc: clock::now(),
+            end_reason: EndReason::Unknown,
             start_mux: 1,
             prev_chunk_start: 0,
             duration: 0,
-            time_accessed: Utc::now(),
-            time_unblocked: Utc::now(),
+            time_accessed: clock::now(),
+            time_unblocked: clock::now(),
             dead: !(rng.gen_bool(percent_pore)),
+            block_start: None,
+            permanent_block: false,
+            last_health_check: 0,
             last_read_len: 0,
             pause: 0.0,
             read_sample_name: String::from(""),
+            segments: vec![],
+            classification: STRAND_CLASSIFICATION,
+            next_molecule: None,
+            molecule: None,
         };
         if !read_info.dead {
             alive += 1
@@ -1094,33 +1980,19 @@ fn setup_channel_vec(
     alive
 }
 
-/// Generate an inital read, which is stored as a ReadInfo in the channel_read_info vec. This is mutated in place.
-fn generate_read(
-    samples: &[String],
-    value: &mut ReadInfo,
-    dist: &WeightedIndex<usize>,
-    views: &HashMap<String, SampleInfo>,
+/// Choose a molecule from a sample and get its signal, with the barcodes for either end if the sample is barcoded.
+/// The length of the molecule in samples is drawn from the sample's read length distribution, unless `read_length` is given,
+/// and the barcode is chosen by weight, unless `barcode_choice` is given.
+fn generate_segment(
+    sample_name: &str,
+    sample_info: &SampleInfo,
     rng: &mut StdRng,
-    read_number: &mut u32,
-    start_time: &u64,
     barcode_squig: &HashMap<String, (Vec<i16>, Vec<i16>)>,
-) {
-    // set stop receieivng to false so we don't accidentally not send the read
-    value.stop_receiving = false;
-    // update as this read hasn't yet been unblocked
-    value.was_unblocked = false;
-    // signal psoitive end_reason
-    value.end_reason = 1;
-    // we want to write this out at the end
-    value.write_out = true;
-    // read start time in samples (seconds since start of experiment * 4000)
-    value.start_time = (Utc::now().timestamp() as u64 - start_time) * 4000_u64;
-    value.start_time_seconds = (Utc::now().timestamp() as u64 - start_time) as usize;
-    value.start_time_utc = Utc::now();
-    value.read_number = *read_number;
-    let sample_choice: &String = &samples[dist.sample(rng)];
-    value.read_sample_name = sample_choice.clone();
-    let sample_info: &SampleInfo = &views[sample_choice];
+    settings: &ReadSettings,
+    read_length: Option<f64>,
+    barcode_choice: Option<usize>,
+) -> Segment {
+    let sample_rate = settings.sample_rate;
     // choose a barcode if we need to - else we always use the first distirbution in the vec
     let mut file_weight_choice: usize = 0;
     let mut barcode = None;
@@ -1128,7 +2000,8 @@ fn generate_read(
     if sample_info.is_barcoded {
         // this is analagous to the choice of the barcode as well - the file weights are in vec with one weight per barcode
         // This is so we can have uneven amplicon coverage, with a different uneveness per barcode
-        file_weight_choice = sample_info.barcode_weights.as_ref().unwrap().sample(rng);
+        file_weight_choice = barcode_choice
+            .unwrap_or_else(|| sample_info.barcode_weights.as_ref().unwrap().sample(rng));
         barcode = Some(
             sample_info
                 .barcodes
@@ -1150,66 +2023,215 @@ fn generate_read(
         )
         .unwrap();
     // earliest possible start point in file, match is for amplicons so we don't start halfway through
-    let start: usize = match sample_info.is_amplicon {
+    let start: usize = match sample_info.is_amplicon || settings.rna {
         true => 0,
         false => rng.gen_range(0..file_info.contig_len - 1000),
     };
-    // Get our distribution from either the Sample specified Gamma or the global read length
+    // Get the sample's read length distribution, which draws lengths in samples
     let read_distribution = &sample_info.read_len_dist;
-    let read_length: usize = read_distribution.sample(rng) as usize;
+    let read_length_samples =
+        |rng: &mut StdRng| read_length.unwrap_or_else(|| read_distribution.sample(rng));
+    let read_length: usize = match (
+        sample_info.partial_amplicon_chance,
+        &file_info.view,
+        &file_info.bases,
+    ) {
+        // primer scheme amplicons are read end to end, unless the read stops part way
+        (Some(partial_chance), _, _) if read_length.is_none() => {
+            match rng.gen_bool(partial_chance) {
+                true => rng.gen_range(1..file_info.contig_len.max(2)),
+                false => file_info.contig_len,
+            }
+        }
+        // squiggle files are at the static sample rate, so take enough of it to cover the read once it is resampled
+        (_, Some(_), _) => {
+            (read_length_samples(rng) * STATIC_SIGNAL_SAMPLE_RATE / sample_rate) as usize
+        }
+        // packed sequence is in bases, not samples
+        (_, None, Some(_)) => (read_length_samples(rng) / settings.samples_per_base) as usize,
+        (_, None, None) => read_length_samples(rng) as usize,
+    };
     // don;t over slice our read
     let end: usize = cmp::min(start + read_length, file_info.contig_len - 1);
-    let (mut barcode_1_squig, mut barcode_2_squig) = (vec![], vec![]);
     // Barcode name has been provided for this sample
-    if sample_info.is_barcoded {
-        (barcode_1_squig, barcode_2_squig) = barcode_squig.get(barcode.unwrap()).unwrap().clone();
-    }
-    let mut squiggle = match sample_info.pore_type {
-        PoreType::R9 => {
-            let mut read_squig = file_info
-                .view
-                .as_ref()
-                .expect("Error unwraping signal view")
-                .slice(s![start..end])
-                .to_vec();
-            if sample_info.is_barcoded {
-                read_squig.extend(barcode_2_squig);
-                barcode_1_squig.extend(read_squig);
-                read_squig = barcode_1_squig;
+    let barcode_squigs = barcode.map(|barcode| barcode_squig.get(barcode).unwrap().clone());
+    // bases of the read that are modified, by their position in the read
+    let mut modified = vec![];
+    let read_squig = match &file_info.view {
+        // precalculated squiggle from make_squiggle.py
+        Some(view) => resample(
+            view.slice(s![start..end]).to_vec(),
+            STATIC_SIGNAL_SAMPLE_RATE,
+            sample_rate,
+        ),
+        // signal simulated from sequence with the pore model, simulating it now if we only kept the bases
+        None => match &file_info.bases {
+            Some(bases) => {
+                let simulator = sample_info
+                    .simulator
+                    .as_ref()
+                    .expect("Packed sequence without a pore model to simulate it");
+                // each kmer starts at a base, so take the bases that finish the last kmer too
+                let sequence = bases.unpack(start, end + simulator.model.k - 1);
+                match &sample_info.modifications {
+                    Some(modifications) => {
+                        let reference = file_info
+                            .reference
+                            .as_ref()
+                            .expect("Modified contig without its place on the reference");
+                        modified = modifications.modified_bases(reference, &sequence, start, rng);
+                        r10_sim::modified_sequence_to_signal(
+                            &simulator.model,
+                            simulator
+                                .modified_model
+                                .as_ref()
+                                .expect("Modified sample without a modified pore model"),
+                            &modified,
+                            &sequence,
+                            &simulator.profile,
+                            rng,
+                            &ProgressBar::hidden(),
+                        )
+                    }
+                    None => r10_sim::sequence_to_signal(
+                        &simulator.model,
+                        &sequence,
+                        &simulator.profile,
+                        rng,
+                        &ProgressBar::hidden(),
+                    ),
+                }
             }
-            read_squig
-        }
-        PoreType::R10 => {
-            // generate a prefix
-            let mut prefix = r10_sim::generate_prefix().expect("NO PREFIX BAD");
-            //  read the signal here
-            let mut read_squig = file_info
+            None => file_info
                 .sequence
                 .as_ref()
                 .expect("Couldn't get my hands on that tasty tasty signal")[start..end]
-                .to_vec();
-            if sample_info.is_barcoded {
-                read_squig.extend(barcode_2_squig);
-                // add on some end padding to see if it improves basecalling
-                read_squig.extend(&prefix);
-                barcode_1_squig.extend(read_squig);
-                read_squig = barcode_1_squig;
+                .to_vec(),
+        },
+    };
+    Segment {
+        signal: read_squig,
+        barcodes: barcode_squigs,
+        simulated: file_info.view.is_none(),
+        source: ReadSegment {
+            sample: sample_name.to_string(),
+            contig: file_info.name.clone(),
+            haplotype: file_info.haplotype,
+            contig_start: start,
+            contig_end: end,
+            signal_start: 0,
+            modified: modified
+                .into_iter()
+                .filter(|(position, _)| start + position < end)
+                .map(|(position, modification)| (start + position, modification))
+                .collect(),
+        },
+    }
+}
+
+/// Generate an inital read, which is stored as a ReadInfo in the channel_read_info vec. This is mutated in place.
+fn generate_read(
+    samples: &[String],
+    value: &mut ReadInfo,
+    molecule: Molecule,
+    dist: &WeightedIndex<usize>,
+    views: &HashMap<String, SampleInfo>,
+    rng: &mut StdRng,
+    read_number: &mut u32,
+    start_time: &u64,
+    barcode_squig: &HashMap<String, (Vec<i16>, Vec<i16>)>,
+    settings: &ReadSettings,
+) {
+    let sample_rate = settings.sample_rate;
+    // set stop receieivng to false so we don't accidentally not send the read
+    value.stop_receiving = false;
+    // update as this read hasn't yet been unblocked
+    value.was_unblocked = false;
+    // signal positive end_reason, unless something cuts the read short
+    value.end_reason = EndReason::SignalPositive;
+    // we want to write this out at the end
+    value.write_out = true;
+    // read start time in samples (seconds since start of experiment * sample rate)
+    let now = clock::now();
+    value.start_time = (now.timestamp() as u64 - start_time) * sample_rate as u64;
+    value.start_time_seconds = (now.timestamp() as u64 - start_time) as usize;
+    value.start_time_utc = now;
+    value.read_number = *read_number;
+    value.molecule = Some(molecule.clone());
+    let sample_choice: &String = &molecule.sample;
+    value.read_sample_name = sample_choice.clone();
+    value.classification = STRAND_CLASSIFICATION;
+    value.segments = vec![];
+    let event = molecule.event;
+    let mut squiggle = match event {
+        EventKind::AdapterOnly => {
+            value.classification = ADAPTER_CLASSIFICATION;
+            settings.structure.adapter_only(rng)
+        }
+        EventKind::Noise => {
+            let (classification, signal) = settings.junk.noise(sample_rate, rng);
+            value.classification = classification;
+            signal
+        }
+        EventKind::Read | EventKind::ShortFragment => {
+            let mut segments = vec![generate_segment(
+                sample_choice,
+                &views[sample_choice],
+                rng,
+                barcode_squig,
+                settings,
+                molecule.length,
+                molecule.barcode,
+            )];
+            // chimeric reads carry on into another molecule, from any sample
+            while event == EventKind::Read
+                && segments.len() < MAX_CHIMERA_SEGMENTS
+                && rng.gen_bool(views[sample_choice].chimera_rate)
+            {
+                let next_sample = &samples[dist.sample(rng)];
+                segments.push(generate_segment(
+                    next_sample,
+                    &views[next_sample],
+                    rng,
+                    barcode_squig,
+                    settings,
+                    None,
+                    None,
+                ));
             }
-            prefix.extend(read_squig);
-            prefix
+            // a chimera can mix samples from squiggle files and sequence, so only scale the simulated molecules
+            let read_scale = settings.read_scale.sample(rng);
+            let read_shift = settings.read_shift.sample(rng);
+            for segment in segments.iter_mut().filter(|segment| segment.simulated) {
+                r10_sim::scale_and_shift(&mut segment.signal, read_scale, read_shift);
+            }
+            let (squiggle, read_segments) = settings.structure.assemble(segments, rng);
+            value.segments = read_segments;
+            squiggle
         }
     };
 
     // slice the view to get our full read
     value.read.append(&mut squiggle);
+    // decide whether the pore is going to block part way through this read, the rest of the read being blocked signal
+    value.permanent_block = settings.lifetime.permanent_block(value.read.len(), rng);
+    value.block_start = None;
+    if value.permanent_block || rng.gen_bool(settings.lifetime.pore_block_chance) {
+        let block_start = rng.gen_range(0..=value.read.len());
+        value.read.truncate(block_start);
+        value.read.extend(settings.lifetime.blocked_signal(rng));
+        value.block_start = Some(block_start);
+        // unless a client unblocks it first, MinKNOW clears the block at the end of the blocked signal
+        value.end_reason = EndReason::UnblockMuxChange;
+    }
     // set estimated duration in seconds
-    value.duration = value.read.len() / 4000;
+    value.duration = (value.read.len() as f64 / sample_rate) as usize;
     // set the read len for channel death chance
     value.last_read_len = value.read.len() as u64;
     let read_id = Uuid::new_v4().to_string();
     value.read_id = read_id;
     // reset these time based metrics
-    value.time_accessed = Utc::now();
+    value.time_accessed = clock::now();
     // prev chunk start has to be zero as there are now no previous chunks on anew read
     value.prev_chunk_start = 0;
 }
@@ -1221,14 +2243,21 @@ impl DataServiceServicer {
         output_path: PathBuf,
         channel_size: usize,
         graceful_shutdown: Arc<Mutex<bool>>,
+        write_out_done: SyncSender<usize>,
     ) -> DataServiceServicer {
         let now = Instant::now();
-        let config = _load_toml(&cli_opts.simulation_profile);
+        let config = _load_toml(cli_opts.get_simulation_profile());
 
         let working_pore_percent = config.get_working_pore_precent();
         let break_chunks_ms: u64 = config.parameters.get_chunk_size_ms();
-        let start_time: u64 = Utc::now().timestamp() as u64;
-        let barcode_squig = create_barcode_squig_hashmap(&config);
+        let sample_rate = config.get_sample_rate();
+        let bases_per_ms = config.get_bases_per_second() / 1000.0;
+        let mux_scan_interval = config.parameters.get_mux_scan_interval_seconds();
+        let read_settings = ReadSettings::new(&config);
+        let start_time: u64 = clock::now().timestamp() as u64;
+        // only loaded if barcodes or samples need signal simulating from sequence
+        let mut simulator: Option<Arc<r10_sim::Simulator>> = None;
+        let barcode_squig = create_barcode_squig_hashmap(&config, &mut simulator);
         info!("Barcodes available {:#?}", barcode_squig.keys());
         let safe: Arc<Mutex<Vec<ReadInfo>>> =
             Arc::new(Mutex::new(Vec::with_capacity(channel_size)));
@@ -1240,45 +2269,63 @@ impl DataServiceServicer {
         let is_setup = Arc::new(Mutex::new(run_setup));
         let is_safe_setup = Arc::clone(&is_setup);
 
-        let (views, dist) = process_samples_from_config(&config);
+        let (views, dist) = process_samples_from_config(&config, simulator);
         let files: Vec<String> = views.keys().cloned().collect();
-        let write_out_gracefully = Arc::clone(&graceful_shutdown);
         let complete_read_tx =
-            start_write_out_thread(run_id, cli_opts, output_path, write_out_gracefully);
+            start_write_out_thread(run_id, cli_opts, output_path, write_out_done);
         let mut rng: StdRng = rand::SeedableRng::seed_from_u64(1234567);
 
         let starting_functional_pore_count =
             setup_channel_vec(channel_size, &thread_safe, &mut rng, working_pore_percent);
-        let death_chance = config.calculate_death_chance(starting_functional_pore_count);
+        let mean_read_lengths = views
+            .iter()
+            .map(|(name, sample_info)| (name.clone(), sample_info.read_len_dist.mean_bases()))
+            .collect();
+        let death_chance =
+            config.calculate_death_chance(starting_functional_pore_count, &mean_read_lengths);
+        let capture = config.get_capture().get_capture_model();
+        let mut library = build_library(&config);
         let mut time_logged_at: f64 = 0.0;
         info!("Death chances {:#?}", death_chance);
         // start the thread to generate data
         thread::spawn(move || {
-            let r: ReacquisitionPoisson = ReacquisitionPoisson::new(1.0, 0.0, 0.0001, 0.05);
-
             // read number for adding to unblock
             let mut read_number: u32 = 0;
             let mut completed_reads: u32 = 0;
+            // total of every time to capture drawn, to report the mean
+            let mut total_time_to_capture: f64 = 0.0;
+            let mut captures: u32 = 0;
+            let mut next_mux_scan = mux_scan_interval;
 
             // Infinte loop for data generation
             loop {
-                let read_process = Instant::now();
+                let read_process = clock::now();
                 debug!("Sequencer mock loop start");
                 let mut new_reads = 0;
                 let mut dead_pores = 0;
                 let mut empty_pores = 0;
                 let mut awaiting_reacquisition = 0;
                 let mut occupied = 0;
+                let mut blocked_pores = 0;
                 // sleep the length of the milliseconds chunk size
                 // Don't sleep the thread just reacquire reads
-                thread::sleep(Duration::from_millis(10));
+                clock::sleep(Duration::from_millis(10));
 
                 // get some basic stats about what is going on at each channel
                 let _channels_with_reads = 0;
+                // a mux scan ends every read that is currently being sequenced
+                let mux_scan = match next_mux_scan {
+                    Some(scan_at) if clock::now().timestamp() as u64 - start_time >= scan_at => {
+                        info!("Mux scan at {} seconds", scan_at);
+                        next_mux_scan = mux_scan_interval.map(|interval| scan_at + interval);
+                        true
+                    }
+                    _ => false,
+                };
                 let mut num = thread_safe.lock().unwrap();
 
                 for i in 0..channel_size {
-                    let time_taken = read_process.elapsed().as_secs_f64();
+                    let time_taken = clock::seconds_since(read_process);
                     let value = num.get_mut(i).unwrap();
                     if value.dead {
                         dead_pores += 1;
@@ -1296,35 +2343,88 @@ impl DataServiceServicer {
                     }
                     let read_estimated_finish_time = value.start_time_seconds + value.duration;
                     // experiment_time is the time the experimanet has started until now
-                    let experiment_time = Utc::now().timestamp() as u64 - start_time;
+                    let experiment_time = clock::now().timestamp() as u64 - start_time;
+                    // cut the read short if it is interrupted by a mux scan or the pore blocking
+                    if value.write_out && !value.was_unblocked && mux_scan {
+                        end_read_early(value, EndReason::MuxChange);
+                    }
+                    let read_seconds =
+                        (experiment_time as usize).saturating_sub(value.start_time_seconds);
+                    if value.write_out
+                        && !value.was_unblocked
+                        && value.block_start.is_some_and(|block_start| {
+                            read_seconds as f64 * sample_rate >= block_start as f64
+                        })
+                    {
+                        blocked_pores += 1;
+                    }
                     // info!("exp time: {}, read_finish_time: {}, is exp greater {}", experiment_time, read_estimated_finish_time, experiment_time as usize > read_estimated_finish_time);
                     // We should deal with this read as if it had finished
                     if experiment_time as usize > read_estimated_finish_time || value.was_unblocked
                     {
                         if value.write_out {
                             completed_reads += 1;
-                            complete_read_tx.send(value.clone()).unwrap();
-                            value.pause = r.sample(&mut rng);
-                            // The potnetial chance to die
-                            let potential_yolo_death =
-                                death_chance.get(&value.read_sample_name).unwrap();
-                            // all our death chances are altered by yield, so we need to change the chance of death of a read was unblocked due to the lowered yield
-                            let prev_chance_multiplier = match value.was_unblocked {
-                                // we unblocked the read and now we need to alter teh chance of death to be lower as the read was lower
-                                true => {
-                                    let unblock_time = value.time_unblocked;
-                                    let read_start_time = value.start_time_utc;
-                                    let elapsed_time =
-                                        (unblock_time - read_start_time).num_milliseconds();
-                                    // convert the elapsed time into a very rough amount of bases
-                                    (elapsed_time as f64 * 0.45)
-                                        / potential_yolo_death.mean_read_length
+                            let lifetime = &read_settings.lifetime;
+                            let age = experiment_time - value.last_health_check;
+                            value.last_health_check = experiment_time;
+                            let worn_out = match lifetime.age_death_chance(age) {
+                                Some(chance) => rng.gen_bool(chance),
+                                None => {
+                                    // The potnetial chance to die
+                                    let potential_yolo_death =
+                                        death_chance.get(&value.read_sample_name).unwrap();
+                                    // all our death chances are altered by yield, so we need to change the chance of death of a read was unblocked due to the lowered yield
+                                    let prev_chance_multiplier = match value.was_unblocked {
+                                        // we unblocked the read and now we need to alter teh chance of death to be lower as the read was lower
+                                        true => {
+                                            let unblock_time = value.time_unblocked;
+                                            let read_start_time = value.start_time_utc;
+                                            let elapsed_time =
+                                                (unblock_time - read_start_time).num_milliseconds();
+                                            // convert the elapsed time into a very rough amount of bases
+                                            (elapsed_time as f64 * bases_per_ms)
+                                                / potential_yolo_death.mean_read_length
+                                        }
+                                        false => 1.0,
+                                    };
+                                    rng.gen_bool(
+                                        potential_yolo_death.base_chance * prev_chance_multiplier,
+                                    )
                                 }
-                                false => 1.0,
                             };
-                            value.dead = rng.gen_bool(
-                                potential_yolo_death.base_chance * prev_chance_multiplier,
+                            // reversing the voltage to unblock can damage the pore
+                            let unblocked = matches!(
+                                value.end_reason,
+                                EndReason::UnblockMuxChange
+                                    | EndReason::DataServiceUnblockMuxChange
                             );
+                            value.dead = worn_out
+                                || value.permanent_block
+                                || (unblocked && rng.gen_bool(lifetime.unblock_death_chance));
+                            // the pore wore out part way through the read, so the signal drops out at some point in it
+                            if worn_out && !value.was_unblocked && value.block_start.is_none() {
+                                let read_len_ms =
+                                    (value.read.len() as f64 / sample_rate * 1000.0) as i64;
+                                value.was_unblocked = true;
+                                value.time_unblocked = value.start_time_utc
+                                    + chrono::Duration::milliseconds(
+                                        rng.gen_range(0..=read_len_ms),
+                                    );
+                                value.end_reason = EndReason::SignalNegative;
+                            }
+                            // a rejected strand can go back into the library
+                            if let (EndReason::DataServiceUnblockMuxChange, Some(molecule)) =
+                                (&value.end_reason, &value.molecule)
+                            {
+                                if is_strand(molecule.event) {
+                                    library.reject(
+                                        &molecule.sample,
+                                        molecule.barcode.unwrap_or(0),
+                                        &mut rng,
+                                    );
+                                }
+                            }
+                            complete_read_tx.send(value.clone()).unwrap();
                         }
                         value.read.clear();
                         // shrink the vec allocation to new empty status
@@ -1334,40 +2434,87 @@ impl DataServiceServicer {
                         value.write_out = false;
                         // Our pore died, so sad
                         if value.dead {
+                            put_back_next_molecule(value, &mut library);
                             dead_pores += 1;
                             continue;
                         }
-                        // chance to aquire a read
-                        if rng.gen_bool(0.8) {
-                            new_reads += 1;
-                            read_number += 1;
-                            generate_read(
+                        // the pore waits for the time to capture its next molecule, then starts reading it
+                        match value.next_molecule.take() {
+                            None => match Molecule::choose(
                                 &files,
-                                value,
                                 &dist,
                                 &views,
+                                &read_settings,
+                                &mut library,
                                 &mut rng,
-                                &mut read_number,
-                                &start_time,
-                                &barcode_squig,
-                            )
+                            ) {
+                                Some((molecule, length)) => {
+                                    value.pause = capture.time_to_capture(
+                                        experiment_time as f64,
+                                        length,
+                                        &mut rng,
+                                    );
+                                    value.next_molecule = Some(molecule);
+                                    total_time_to_capture += value.pause;
+                                    captures += 1;
+                                }
+                                // nothing left in the library to capture, so look again later
+                                None => value.pause = LIBRARY_RETRY_SECONDS,
+                            },
+                            Some(molecule) => {
+                                new_reads += 1;
+                                read_number += 1;
+                                generate_read(
+                                    &files,
+                                    value,
+                                    molecule,
+                                    &dist,
+                                    &views,
+                                    &mut rng,
+                                    &mut read_number,
+                                    &start_time,
+                                    &barcode_squig,
+                                    &read_settings,
+                                )
+                            }
                         }
                     }
                 }
                 let _end = now.elapsed().as_secs_f64();
                 if _end.ceil() > time_logged_at {
                     info!(
-                        "New reads: {}, Occupied: {}, Empty pores: {}, Dead pores: {}, Sequenced reads: {}, Awaiting: {}",
-                        new_reads, occupied, empty_pores, dead_pores, completed_reads, awaiting_reacquisition
+                        "New reads: {}, Occupied: {}, Blocked: {}, Empty pores: {}, Dead pores: {}, Sequenced reads: {}, Awaiting: {}, Mean time to capture: {:.3}s (model {:.3}s)",
+                        new_reads, occupied, blocked_pores, empty_pores, dead_pores, completed_reads, awaiting_reacquisition,
+                        total_time_to_capture / captures.max(1) as f64,
+                        capture.mean_time_to_capture((clock::now().timestamp() as u64 - start_time) as f64)
                     );
+                    if library.is_finite() && (time_logged_at as u64).is_multiple_of(60) {
+                        info!("Library molecules left: {}", library.summary());
+                    }
                     time_logged_at = _end.ceil();
                 }
-                {
-                    if *graceful_shutdown.lock().unwrap() {
-                        break;
+                let stopping = { *graceful_shutdown.lock().unwrap() }
+                    || dead_pores >= (0.99 * channel_size as f64) as usize;
+                if stopping {
+                    // the run has been stopped, so any reads still being sequenced end now
+                    for value in num
+                        .iter_mut()
+                        .filter(|value| value.write_out && !value.read.is_empty())
+                    {
+                        if !value.was_unblocked {
+                            end_read_early(value, EndReason::ApiRequest);
+                        }
+                        completed_reads += 1;
+                        complete_read_tx.send(value.clone()).unwrap();
                     }
-                }
-                if dead_pores >= (0.99 * channel_size as f64) as usize {
+                    // molecules waiting to be captured are never read
+                    for value in num.iter_mut() {
+                        put_back_next_molecule(value, &mut library);
+                    }
+                    info!(
+                        "Stopping data generation, {} reads sequenced",
+                        completed_reads
+                    );
                     *graceful_shutdown.lock().unwrap() = true;
                     break;
                 }
@@ -1380,6 +2527,7 @@ impl DataServiceServicer {
             setup: is_safe_setup,
             break_chunks_ms,
             channel_size,
+            sample_rate,
         }
     }
 }
@@ -1403,7 +2551,8 @@ impl DataService for DataServiceServicer {
         let channel_size = self.channel_size;
         let mut stream_counter = 1;
         let break_chunk_ms = self.break_chunks_ms;
-        let chunk_size = break_chunk_ms as f64 / 1000.0 * 4000.0;
+        let sample_rate = self.sample_rate;
+        let chunk_size = break_chunk_ms as f64 / 1000.0 * sample_rate;
 
         // Stream the responses back
         let output = async_stream::try_stream! {
@@ -1432,11 +2581,11 @@ impl DataService for DataServiceServicer {
                     let mut channel: u32 = 1;
                     let mut num_reads_stop_receiving: usize = 0;
                     let mut num_channels_empty: usize = 0;
-                    // max read len in samples that we will consider sending samples for
-                    let max_read_len_samples: usize = 30000;
+                    // max read len in samples that we will consider sending samples for - 7.5 seconds worth
+                    let max_read_len_samples: usize = (7.5 * sample_rate) as usize;
 
-                    // calculate number of samples to slice - roughly the time we break reads * 4000, so for the default 0.4 seconds
-                    // we serve 0.4 * 4000 (1600) samples
+                    // calculate number of samples to slice - roughly the time we break reads * sample rate, so for the default 0.4 seconds
+                    // at 4000 Hz we serve 0.4 * 4000 (1600) samples
 
                     // The below code block allows us to Send the responses across an await.
                     {
@@ -1455,11 +2604,11 @@ impl DataService for DataServiceServicer {
                             if !read_info.stop_receiving && !read_info.was_unblocked && read_info.read.len() > 0 {
                                 // work out where to start and stop our slice of signal
                                 let mut start = read_info.prev_chunk_start;
-                                let now_time = Utc::now();
+                                let now_time = clock::now();
                                 let read_start_time = read_info.start_time_utc;
-                                let elapsed_time = now_time.time() - read_start_time.time();
+                                let elapsed_time = now_time - read_start_time;
                                 // How far through the read we are in total samples
-                                let mut stop = convert_milliseconds_to_samples(elapsed_time.num_milliseconds());
+                                let mut stop = convert_milliseconds_to_samples(elapsed_time.num_milliseconds(), sample_rate);
                                 // slice of signal is too short
                                 if start > stop || (stop - start) < chunk_size as usize {
                                     continue
@@ -1498,13 +2647,18 @@ impl DataService for DataServiceServicer {
                                 if read_chunk.len() < 300 {
                                     continue
                                 }
+                                // once the pore has blocked the chunks are blocked signal, not strand
+                                let classification = match read_info.block_start {
+                                    Some(block_start) if stop > block_start => BLOCKED_CLASSIFICATION,
+                                    _ => read_info.classification,
+                                };
                                 container.push((read_info.channel, ReadData{
                                         id: read_info.read_id.clone(),
                                         number: read_info.read_number.clone(),
                                         start_sample: 0,
                                         chunk_start_sample: 0,
                                         chunk_length:  read_chunk.len() as u64,
-                                        chunk_classifications: vec![83],
+                                        chunk_classifications: vec![classification],
                                         raw_data: convert_to_u8(read_chunk),
                                         median_before: 225.0,
                                         median: 110.0,
@@ -1535,7 +2689,7 @@ impl DataService for DataServiceServicer {
                         channel_data.clear();
                     }
                     container.clear();
-                    thread::sleep(Duration::from_millis(break_chunk_ms));
+                    clock::sleep(Duration::from_millis(break_chunk_ms));
                 }
 
             });
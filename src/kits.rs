@@ -0,0 +1,174 @@
+//! Known flow cells and sequencing kits, and the run metadata that MinKNOW would write out for them.
+//!
+//! Downstream tools pick basecalling models from the flow cell product code, sequencing kit and basecall config in the
+//! `tracking_id` and `context_tags`, so the defaults used for a simulation have to agree with each other. Each flow cell
+//! maps onto a [`Chemistry`], which provides the default kit and the software versions that were current for it.
+//!
+
+use crate::PoreType;
+
+/// The pore chemistry of a flow cell, along with the software versions MinKNOW was running when it was in use.
+#[derive(Debug)]
+pub struct Chemistry {
+    /// The kit used with this chemistry if none is given
+    pub default_kit: &'static str,
+    /// The basecall configs for DNA reads, with the sample rate in Hz each is for
+    pub dna_basecall_configs: &'static [(u32, &'static str)],
+    /// The basecall config for RNA reads, if RNA can be sequenced on this chemistry
+    pub rna_basecall_config: Option<&'static str>,
+    /// Guppy version
+    pub guppy_version: &'static str,
+    /// MinKNOW core version
+    pub minknow_version: &'static str,
+    /// MinKNOW distribution version
+    pub distribution_version: &'static str,
+    /// MinKNOW configuration version
+    pub configuration_version: &'static str,
+    /// Bream (protocols) version
+    pub protocols_version: &'static str,
+}
+
+/// R9.4.1 pores, sequenced with the LSK109 family of kits
+pub const R9_4_1: Chemistry = Chemistry {
+    default_kit: "SQK-LSK109",
+    dna_basecall_configs: &[(4000, "dna_r9.4.1_450bps_hac.cfg")],
+    rna_basecall_config: Some("rna_r9.4.1_70bps_hac.cfg"),
+    guppy_version: "5.0.17+99baa5b",
+    minknow_version: "4.4.3",
+    distribution_version: "21.10.8",
+    configuration_version: "4.4.13",
+    protocols_version: "6.3.5",
+};
+
+/// R10.4.1 pores, sequenced with the kit 14 family of kits
+pub const R10_4_1: Chemistry = Chemistry {
+    default_kit: "SQK-LSK114",
+    dna_basecall_configs: &[
+        (4000, "dna_r10.4.1_e8.2_400bps_hac.cfg"),
+        (5000, "dna_r10.4.1_e8.2_400bps_5khz_hac.cfg"),
+    ],
+    rna_basecall_config: None,
+    guppy_version: "6.4.6+ae70e8f",
+    minknow_version: "5.4.3",
+    distribution_version: "22.12.7",
+    configuration_version: "5.4.7",
+    protocols_version: "7.4.8",
+};
+
+/// RNA pores, only sequenced with the RNA004 kit
+pub const RNA_RP4: Chemistry = Chemistry {
+    default_kit: "SQK-RNA004",
+    // nothing but RNA can be sequenced on these flow cells
+    dna_basecall_configs: &[(4000, "rna_rp4_130bps_hac.cfg")],
+    rna_basecall_config: Some("rna_rp4_130bps_hac.cfg"),
+    guppy_version: "7.1.4+d9ed22f",
+    minknow_version: "5.7.5",
+    distribution_version: "23.07.12",
+    configuration_version: "5.7.11",
+    protocols_version: "7.7.11",
+};
+
+impl Chemistry {
+    /// The basecall config for DNA reads at a sample rate, or for the closest sample rate there is a config for.
+    pub fn dna_basecall_config(&self, sample_rate: f64) -> &'static str {
+        self.dna_basecall_configs
+            .iter()
+            .min_by(|(a, _), (b, _)| {
+                (*a as f64 - sample_rate)
+                    .abs()
+                    .total_cmp(&(*b as f64 - sample_rate).abs())
+            })
+            .map(|(_, config)| *config)
+            .unwrap()
+    }
+}
+
+/// Flow cell product codes we know about, and their chemistry
+const FLOW_CELLS: [(&str, &Chemistry); 8] = [
+    ("FLO-MIN106", &R9_4_1),
+    ("FLO-FLG001", &R9_4_1),
+    ("FLO-PRO002", &R9_4_1),
+    ("FLO-MIN114", &R10_4_1),
+    ("FLO-FLG114", &R10_4_1),
+    ("FLO-PRO114M", &R10_4_1),
+    ("FLO-MIN004RA", &RNA_RP4),
+    ("FLO-PRO004RA", &RNA_RP4),
+];
+
+/// Get the chemistry of a known flow cell product code, ignoring case.
+pub fn flow_cell_chemistry(product_code: &str) -> Option<&'static Chemistry> {
+    FLOW_CELLS
+        .iter()
+        .find(|(code, _)| code.eq_ignore_ascii_case(product_code))
+        .map(|(_, chemistry)| *chemistry)
+}
+
+/// The chemistry that matches the pore type being simulated.
+pub fn pore_type_chemistry(pore_type: &PoreType) -> &'static Chemistry {
+    match pore_type {
+        PoreType::R9 => &R9_4_1,
+        PoreType::R10 => &R10_4_1,
+    }
+}
+
+/// The flow cell used for a pore type if none is given.
+pub fn default_flow_cell(pore_type: &PoreType) -> &'static str {
+    match pore_type {
+        PoreType::R9 => "FLO-MIN106",
+        PoreType::R10 => "FLO-MIN114",
+    }
+}
+
+/// The device type a flow cell is sequenced on - PromethION flow cells need a PromethION, everything else fits a MinION.
+pub fn device_type(flow_cell: &str) -> &'static str {
+    match flow_cell.to_uppercase().starts_with("FLO-PRO") {
+        true => "promethion",
+        false => "minion",
+    }
+}
+
+/// Whether a sequencing kit is a direct RNA kit.
+pub fn is_rna_kit(kit: &str) -> bool {
+    kit.to_uppercase().starts_with("SQK-RNA")
+}
+
+/// A direct RNA sequencing kit, and how its reads are sequenced
+#[derive(Debug)]
+pub struct RnaKit {
+    /// Names of the kit
+    pub names: &'static [&'static str],
+    /// The flow cell the kit is sequenced on if none is given
+    pub flow_cell: &'static str,
+    /// Sample rate in Hz the kit is sequenced at
+    pub sample_rate: u32,
+    /// Translocation speed of the strand, in bases per second
+    pub bases_per_second: f64,
+}
+
+/// Direct RNA on R9.4.1 pores
+pub const RNA002: RnaKit = RnaKit {
+    names: &["SQK-RNA002", "SQK-RNA001"],
+    flow_cell: "FLO-MIN106",
+    sample_rate: 3000,
+    bases_per_second: 70.0,
+};
+
+/// Direct RNA on the RNA flow cells
+pub const RNA004: RnaKit = RnaKit {
+    names: &["SQK-RNA004"],
+    flow_cell: "FLO-MIN004RA",
+    sample_rate: 4000,
+    bases_per_second: 130.0,
+};
+
+/// Get the direct RNA kit a sequencing kit is, or None if it isn't an RNA kit. RNA kits we don't know are sequenced like
+/// RNA002.
+pub fn rna_kit(kit: &str) -> Option<&'static RnaKit> {
+    if !is_rna_kit(kit) {
+        return None;
+    }
+    [&RNA004, &RNA002]
+        .into_iter()
+        .find(|rna_kit| rna_kit.names.iter().any(|x| x.eq_ignore_ascii_case(kit)))
+        .or(Some(&RNA002))
+}
@@ -0,0 +1,115 @@
+//! A finite library of molecules, so adaptive sampling changes what is left to sequence over a long run.
+//!
+//! Samples with a number of molecules set have a pool of that many molecules for each barcode (or one pool if the sample
+//! isn't barcoded). Capturing a molecule takes it out of its pool, so the chance of capturing from a pool falls as it
+//! empties. A molecule rejected by a client of the data service may go back into its pool, while a molecule that is
+//! sequenced is used up. Samples without a number of molecules are never used up.
+//!
+use std::collections::HashMap;
+
+use rand::Rng;
+
+/// The molecules left of one sample and barcode
+#[derive(Debug, Clone)]
+struct Pool {
+    /// Name of the sample, and barcode if it has one, for logging
+    label: String,
+    /// Molecules in the library at the start of the run
+    initial: usize,
+    /// Molecules not yet captured
+    remaining: usize,
+    /// Chance a rejected molecule goes back into the pool
+    return_chance: f64,
+}
+
+/// The molecules left of every sample and barcode with a finite library
+#[derive(Debug, Clone, Default)]
+pub struct Library {
+    /// Pools by sample name and barcode index
+    pools: HashMap<(String, usize), Pool>,
+    /// Whether any sample is never used up
+    unlimited: bool,
+}
+
+impl Library {
+    /// Create an empty library, with no finite pools.
+    pub fn new() -> Library {
+        Library::default()
+    }
+
+    /// Add a pool of molecules for a sample and barcode, the barcode being 0 if the sample isn't barcoded.
+    pub fn add_pool(
+        &mut self,
+        sample: &str,
+        barcode: usize,
+        label: String,
+        molecules: usize,
+        return_chance: f64,
+    ) {
+        self.pools.insert(
+            (sample.to_string(), barcode),
+            Pool {
+                label,
+                initial: molecules,
+                remaining: molecules,
+                return_chance,
+            },
+        );
+    }
+
+    /// Note that a sample is never used up, so the library can't run out.
+    pub fn add_unlimited(&mut self) {
+        self.unlimited = true;
+    }
+
+    /// Whether there are any finite pools.
+    pub fn is_finite(&self) -> bool {
+        !self.pools.is_empty()
+    }
+
+    /// Whether every molecule has been used up, so nothing is left to capture.
+    pub fn is_exhausted(&self) -> bool {
+        !self.unlimited && self.pools.values().all(|pool| pool.remaining == 0)
+    }
+
+    /// Try to take a molecule of a sample and barcode that has been drawn by weight, succeeding with the fraction of the
+    /// pool that is left so pools are captured from in proportion to what remains. Always succeeds for samples that
+    /// are never used up.
+    pub fn try_take<R: Rng>(&mut self, sample: &str, barcode: usize, rng: &mut R) -> bool {
+        let Some(pool) = self.pools.get_mut(&(sample.to_string(), barcode)) else {
+            return true;
+        };
+        if pool.remaining == 0 || !rng.gen_bool(pool.remaining as f64 / pool.initial as f64) {
+            return false;
+        }
+        pool.remaining -= 1;
+        true
+    }
+
+    /// A molecule was rejected, so it may go back into its pool.
+    pub fn reject<R: Rng>(&mut self, sample: &str, barcode: usize, rng: &mut R) {
+        if let Some(pool) = self.pools.get_mut(&(sample.to_string(), barcode)) {
+            if pool.remaining < pool.initial && rng.gen_bool(pool.return_chance) {
+                pool.remaining += 1;
+            }
+        }
+    }
+
+    /// A molecule was taken but never read, as its pore died or the run stopped first, so it goes back into its pool.
+    pub fn put_back(&mut self, sample: &str, barcode: usize) {
+        if let Some(pool) = self.pools.get_mut(&(sample.to_string(), barcode)) {
+            pool.remaining = (pool.remaining + 1).min(pool.initial);
+        }
+    }
+
+    /// The molecules left in each pool, as `label remaining/initial`.
+    pub fn summary(&self) -> String {
+        let mut pools: Vec<&Pool> = self.pools.values().collect();
+        pools.sort_by(|a, b| a.label.cmp(&b.label));
+        pools
+            .iter()
+            .map(|pool| format!("{} {}/{}", pool.label, pool.remaining, pool.initial))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
@@ -12,8 +12,16 @@
 //! Has one position, which is hosted on 127.0.0.1:10001
 //!
 /// The module pertaining the CLI code
+mod barcodes;
 pub mod cli;
+mod clock;
 mod impl_services;
+mod kits;
+mod library;
+mod modifications;
+mod output;
+pub mod pore_model;
+mod primer_scheme;
 pub mod r10_simulation;
 mod reacquisition_distribution;
 pub mod utils;
@@ -24,15 +32,17 @@ extern crate lazy_static;
 mod read_length_distribution;
 /// Import all our definied services
 mod services;
+mod squiggle;
+mod variants;
 
-use chrono::prelude::*;
 use clap::Parser;
 use configparser::ini::Ini;
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::fs;
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::sync_channel;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tonic::transport::{Identity, Server, ServerTlsConfig};
@@ -58,8 +68,10 @@ use crate::services::minknow_api::manager::manager_service_server::ManagerServic
 use crate::services::minknow_api::manager::FlowCellPosition;
 use crate::services::minknow_api::protocol::protocol_service_server::ProtocolServiceServer;
 
-use crate::reacquisition_distribution::{DeathChance, _calculate_death_chance};
-use crate::read_length_distribution::ReadLengthDist;
+use crate::output::slow5::RecordCompression;
+use crate::r10_simulation::DwellDistribution;
+use crate::reacquisition_distribution::{CaptureModel, DeathChance, _calculate_death_chance};
+use crate::read_length_distribution::{EmpiricalLengths, LengthDistribution, ReadLengthDist};
 
 /// Holds the  type of the pore we are simulating
 #[derive(Clone)]
@@ -69,6 +81,18 @@ pub enum PoreType {
     /// R9 pore
     R9,
 }
+/// The file format that finished reads are written out in
+#[derive(Clone, Debug)]
+pub enum OutputFormat {
+    /// Multi read FAST5
+    Fast5,
+    /// POD5
+    Pod5,
+    /// ASCII SLOW5
+    Slow5,
+    /// Binary BLOW5, with the given record compression
+    Blow5(RecordCompression),
+}
 #[derive(Deserialize, Debug, Clone)]
 struct Config {
     parameters: Parameters,
@@ -76,9 +100,27 @@ struct Config {
     output_path: std::path::PathBuf,
     global_mean_read_length: Option<f64>,
     random_seed: Option<u64>,
+    speed_up: Option<f64>,
     target_yield: f64,
     working_pore_percent: Option<usize>,
     pore_type: Option<String>,
+    output_format: Option<String>,
+    blow5_compression: Option<String>,
+    reads_per_file: Option<usize>,
+    flush_interval_seconds: Option<u64>,
+    run_metadata: Option<RunMetadataConfig>,
+    sample_rate: Option<u32>,
+    bases_per_second: Option<f64>,
+    signal: Option<SignalConfig>,
+    read_structure: Option<ReadStructureConfig>,
+    junk_events: Option<JunkEventsConfig>,
+    capture: Option<CaptureConfig>,
+    pore_lifetime: Option<PoreLifetimeConfig>,
+    pore_model: Option<std::path::PathBuf>,
+    signal_cache: Option<std::path::PathBuf>,
+    modified_pore_model: Option<std::path::PathBuf>,
+    barcode_kit: Option<String>,
+    custom_barcodes: Option<HashMap<String, String>>,
 }
 
 impl Config {
@@ -100,21 +142,166 @@ impl Config {
         }
     }
 
-    /// Calculate the chance a pore will die.
-    pub fn calculate_death_chance(&self, starting_channels: usize) -> HashMap<String, DeathChance> {
+    /// Check that we have a valid output format or return the default of FAST5.
+    pub fn check_output_format(&self) -> OutputFormat {
+        match &self.output_format {
+            Some(output_format) => match output_format.to_lowercase().as_str() {
+                "fast5" => OutputFormat::Fast5,
+                "pod5" => OutputFormat::Pod5,
+                "slow5" => OutputFormat::Slow5,
+                "blow5" => OutputFormat::Blow5(self.check_blow5_compression()),
+                _ => {
+                    panic!("Invalid output format specified")
+                }
+            },
+            None => OutputFormat::Fast5,
+        }
+    }
+
+    /// Check that we have a valid BLOW5 record compression or return the default of zlib.
+    pub fn check_blow5_compression(&self) -> RecordCompression {
+        match &self.blow5_compression {
+            Some(compression) => match compression.to_lowercase().as_str() {
+                "none" => RecordCompression::None,
+                "zlib" => RecordCompression::Zlib,
+                "zstd" => RecordCompression::Zstd,
+                _ => {
+                    panic!("Invalid BLOW5 compression specified")
+                }
+            },
+            None => RecordCompression::Zlib,
+        }
+    }
+
+    /// Get the sample rate of the signal in Hz. If not set defaults to the sample rate of a direct RNA kit, or 4000
+    pub fn get_sample_rate(&self) -> f64 {
+        match (self.sample_rate, self.get_rna_kit()) {
+            (Some(sample_rate), _) => sample_rate as f64,
+            (None, Some(rna_kit)) => rna_kit.sample_rate as f64,
+            (None, None) => 4000.0,
+        }
+    }
+
+    /// Get the translocation speed of the strand through the pore. If not set defaults to the speed of a direct RNA kit,
+    /// or 450 for R9 and 400 for R10
+    pub fn get_bases_per_second(&self) -> f64 {
+        match (self.bases_per_second, self.get_rna_kit()) {
+            (Some(bases_per_second), _) => bases_per_second,
+            (None, Some(rna_kit)) => rna_kit.bases_per_second,
+            (None, None) => match self.check_pore_type() {
+                PoreType::R9 => 450.0,
+                PoreType::R10 => 400.0,
+            },
+        }
+    }
+
+    /// Get the number of samples recorded for each base that goes through the pore
+    pub fn get_samples_per_base(&self) -> f64 {
+        self.get_sample_rate() / self.get_bases_per_second()
+    }
+
+    /// Get the path to the pore model used to simulate signal from sequence.
+    /// Defaults to the model shipped in static/ for R10, there is no default for R9 or direct RNA.
+    pub fn get_pore_model_path(&self) -> Option<std::path::PathBuf> {
+        match (&self.pore_model, self.get_rna_kit()) {
+            (Some(path), _) => Some(path.clone()),
+            (None, Some(_)) => None,
+            (None, None) => match self.check_pore_type() {
+                PoreType::R10 => Some(PathBuf::from("static/R10_model.tsv")),
+                PoreType::R9 => None,
+            },
+        }
+    }
+
+    /// Get the path to the table of kmers with modified bases, used to simulate samples with modifications.
+    pub fn get_modified_pore_model_path(&self) -> Option<&std::path::Path> {
+        self.modified_pore_model.as_deref()
+    }
+
+    /// Get the direct RNA kit being simulated, if the sequencing kit in the run metadata is one. Reads of an RNA kit are read
+    /// 3' to 5' and start with a poly(A) tail.
+    pub fn get_rna_kit(&self) -> Option<&'static kits::RnaKit> {
+        self.run_metadata
+            .as_ref()
+            .and_then(|run_metadata| run_metadata.sequencing_kit.as_deref())
+            .and_then(kits::rna_kit)
+    }
+
+    /// Get the barcoding kit that barcode signal is simulated for. Defaults to the sequencing kit in the run metadata, if
+    /// that is a barcoding kit.
+    pub fn get_barcode_kit(&self) -> Option<&'static barcodes::BarcodeKit> {
+        match &self.barcode_kit {
+            Some(kit) => {
+                Some(barcodes::find_kit(kit).unwrap_or_else(|| panic!("Unknown barcode_kit {kit}")))
+            }
+            None => self
+                .run_metadata
+                .as_ref()
+                .and_then(|run_metadata| run_metadata.sequencing_kit.as_deref())
+                .and_then(barcodes::find_kit),
+        }
+    }
+
+    /// Get the signal noise settings, or the defaults if there is no signal section.
+    pub fn get_signal_config(&self) -> SignalConfig {
+        self.signal.clone().unwrap_or_default()
+    }
+
+    /// Get the read structure settings, or the defaults if there is no read_structure section.
+    pub fn get_read_structure(&self) -> ReadStructureConfig {
+        self.read_structure.clone().unwrap_or_default()
+    }
+
+    /// Get the junk event settings, or the defaults (no junk events) if there is no junk_events section.
+    pub fn get_junk_events(&self) -> JunkEventsConfig {
+        self.junk_events.clone().unwrap_or_default()
+    }
+
+    /// Get how many times faster than real time the simulation runs. Default 1.0
+    pub fn get_speed_up(&self) -> f64 {
+        self.speed_up.unwrap_or(1.0)
+    }
+
+    /// Get the capture model settings, or the defaults if there is no capture section.
+    pub fn get_capture(&self) -> CaptureConfig {
+        self.capture.clone().unwrap_or_default()
+    }
+
+    /// Get the pore lifetime settings, or the defaults (pores die to reach the target yield) if there is no pore_lifetime
+    /// section.
+    pub fn get_pore_lifetime(&self) -> PoreLifetimeConfig {
+        self.pore_lifetime.clone().unwrap_or_default()
+    }
+
+    /// Check that we have a valid dwell distribution or return the default fixed dwell.
+    /// The gamma standard deviation defaults to 40% of the mean samples per base.
+    pub fn check_dwell_distribution(&self) -> DwellDistribution {
+        self.get_signal_config()
+            .get_dwell_distribution(self.get_samples_per_base())
+    }
+
+    /// Get the number of reads to write into each output file. If not set a default value of 4000 is returned
+    pub fn get_reads_per_file(&self) -> usize {
+        self.reads_per_file.unwrap_or(4000)
+    }
+
+    /// Get how long to wait before writing out any pending reads, even if there aren't enough to fill a file.
+    /// If not set a default of 60 seconds is returned
+    pub fn get_flush_interval(&self) -> Duration {
+        Duration::from_secs(self.flush_interval_seconds.unwrap_or(60))
+    }
+
+    /// Calculate the chance a pore will die, from the mean read length in bases of each sample's read length distribution.
+    pub fn calculate_death_chance(
+        &self,
+        starting_channels: usize,
+        mean_read_lengths: &HashMap<String, f64>,
+    ) -> HashMap<String, DeathChance> {
         let target_yield = &self.target_yield;
         let mut deaths = HashMap::new();
         for sample in &self.sample {
-            let mean_read_len = match sample.mean_read_length {
-                Some(rl) => rl,
-                None => match self.global_mean_read_length {
-                    Some(rl) => rl,
-                    None => {
-                        panic!("Sample {} does not have a mean read length and no global read length is set.", sample.input_genome.display());
-                    }
-                },
-            };
             let name = &sample.name;
+            let mean_read_len = mean_read_lengths[name];
             let death = DeathChance {
                 base_chance: _calculate_death_chance(
                     starting_channels as f64,
@@ -147,15 +334,198 @@ impl Config {
     // Check config fields and error out if there's a problem
     pub fn check_fields(&self) {
         let _pore_type = &self.check_pore_type();
+        let _output_format = &self.check_output_format();
+        let _dwell_distribution = &self.check_dwell_distribution();
+        // the sequencing kit only picks the barcode kit if there are barcodes to simulate
+        let sequencing_kit = self
+            .run_metadata
+            .as_ref()
+            .and_then(|run_metadata| run_metadata.sequencing_kit.as_deref())
+            .filter(|_| self.sample.iter().any(|sample| sample.is_barcoded()));
+        if let Some(kit) = self
+            .barcode_kit
+            .as_deref()
+            .or(sequencing_kit)
+            .filter(|kit| barcodes::is_unsupported_kit(kit))
+        {
+            panic!("{kit} is a 96 barcode kit, but only barcodes 1 to 24 are built in. Set barcode_kit to the 24 barcode kit of the same chemistry, e.g. SQK-NBD114-24, and give the sequences of any barcodes above 24 in custom_barcodes.")
+        }
+        let _barcode_kit = &self.get_barcode_kit();
+        // no RNA model is shipped, and every RNA read needs one for its poly(A) tail
+        if let Some(rna_kit) = self.get_rna_kit() {
+            if self.pore_model.is_none() {
+                panic!("{} is a direct RNA kit, so pore_model must be set to an RNA kmer model, such as those in ONT's kmer_models repository.", rna_kit.names[0])
+            }
+        }
+        let signal = self.get_signal_config();
+        if signal.get_dwell_stdv(self.get_samples_per_base()) <= 0.0
+            || signal.get_level_noise() < 0.0
+            || signal.get_read_scale_stdv() < 0.0
+            || signal.get_read_shift_stdv() < 0.0
+        {
+            panic!("Signal noise settings must not be negative, and dwell_stdv must be greater than 0.")
+        }
+        let read_structure = self.get_read_structure();
+        if read_structure.get_stall_ms() < 0.0
+            || read_structure.get_poly_a_length() < 0.0
+            || !(0.0..=1.0).contains(&read_structure.get_barcode_chance())
+            || !(0.0..=1.0).contains(&read_structure.get_truncation_chance())
+        {
+            panic!("stall_ms and poly_a_length must not be negative, and barcode_chance and truncation_chance must be between 0 and 1.")
+        }
+        let junk_events = self.get_junk_events();
+        let junk_fractions = [
+            junk_events.get_adapter_only_fraction(),
+            junk_events.get_short_fragment_fraction(),
+            junk_events.get_noise_fraction(),
+        ];
+        if junk_fractions.iter().any(|fraction| *fraction < 0.0)
+            || junk_fractions.iter().sum::<f64>() >= 1.0
+        {
+            panic!("Junk event fractions must not be negative, and must add up to less than 1.")
+        }
+        if junk_events.get_max_short_fragment_bases() <= MIN_SHORT_FRAGMENT_BASES {
+            panic!("max_short_fragment_bases must be more than {MIN_SHORT_FRAGMENT_BASES}.")
+        }
+        let capture = self.get_capture();
+        if capture.get_mean_time_to_capture() <= 0.0
+            || capture.get_concentration() <= 0.0
+            || capture.get_reference_length() <= 0.0
+            || capture
+                .half_life_hours
+                .is_some_and(|half_life| half_life <= 0.0)
+        {
+            panic!("Capture mean_time_to_capture, concentration, reference_length and half_life_hours must be greater than 0.")
+        }
+        let pore_lifetime = self.get_pore_lifetime();
+        if pore_lifetime
+            .get_half_life_seconds()
+            .is_some_and(|half_life| half_life <= 0.0)
+            || pore_lifetime
+                .get_permanent_block_bases()
+                .is_some_and(|bases| bases <= 0.0)
+            || pore_lifetime.get_block_timeout_seconds() < 0.0
+            || !(0.0..=1.0).contains(&pore_lifetime.get_unblock_death_chance())
+            || !(0.0..=1.0).contains(&pore_lifetime.get_pore_block_chance())
+        {
+            panic!("Pore lifetime half_life_hours and permanent_block_bases must be greater than 0, block_timeout_seconds must not be negative and unblock_death_chance and pore_block_chance must be between 0 and 1.")
+        }
+        if let Some(flow_cell) = self
+            .run_metadata
+            .as_ref()
+            .and_then(|run_metadata| run_metadata.flow_cell_product_code.as_ref())
+        {
+            if kits::flow_cell_chemistry(flow_cell).is_none() {
+                warn!(
+                    "Unknown flow cell product code {flow_cell}, using defaults for the pore type"
+                );
+            }
+        }
+        if self.get_sample_rate() <= 0.0 || self.get_bases_per_second() <= 0.0 {
+            panic!("sample_rate and bases_per_second must be greater than 0.")
+        }
+        if self.get_speed_up() <= 0.0 {
+            panic!("speed_up must be greater than 0.")
+        }
+        if self.get_reads_per_file() == 0 {
+            panic!("reads_per_file must be greater than 0.")
+        }
         for sample in &self.sample {
-            match sample.mean_read_length {
-                Some(_) => {}
-                None => match self.global_mean_read_length {
-                    Some(_) => {}
-                    None => {
-                        panic!("Sample {} does not have a mean read length and no global read length is set.", sample.input_genome.display());
-                    }
-                },
+            let read_length = sample.get_read_length();
+            let positive = |value: Option<f64>| value.is_none_or(|value| value > 0.0);
+            if !positive(sample.get_mean_read_length(self.global_mean_read_length))
+                || !positive(read_length.stdv)
+                || !positive(read_length.shape)
+            {
+                panic!(
+                    "Read length mean, stdv and shape for sample {} must be greater than 0.",
+                    sample.name
+                )
+            }
+            if read_length.get_min() < 0.0 || read_length.get_min() > read_length.get_max() {
+                panic!(
+                    "Read length min for sample {} must be at least 0 and no more than max.",
+                    sample.name
+                )
+            }
+            // panics if the distribution can't be made. Empirical lengths are only read once, when the sample is loaded
+            match (read_length.get_distribution(), &read_length.lengths) {
+                ("empirical", Some(lengths)) if !lengths.exists() => panic!(
+                    "Read lengths file {} for sample {} does not exist.",
+                    lengths.display(),
+                    sample.name
+                ),
+                ("empirical", Some(_)) => {}
+                _ => {
+                    sample.get_read_len_dist(self.global_mean_read_length, 1.0);
+                }
+            }
+            if sample.molecules == Some(0)
+                || !(0.0..=1.0).contains(&sample.get_rejected_return_chance())
+            {
+                panic!(
+                    "Sample {} must have more than 0 molecules, and a rejected_return_chance between 0 and 1.",
+                    sample.name
+                )
+            }
+            if sample.scheme_bed.is_some() && sample.input_genome.is_dir() {
+                panic!(
+                    "Sample {} has a scheme_bed, so its input_genome must be a FASTA file.",
+                    sample.name
+                );
+            }
+            if !(0.0..=1.0).contains(&sample.get_amplicon_dropout())
+                || !(0.0..=1.0).contains(&sample.get_partial_amplicon_chance())
+            {
+                panic!(
+                    "amplicon_dropout and partial_amplicon_chance for sample {} must be between 0 and 1.",
+                    sample.name
+                );
+            }
+            if let Some(variants) = &sample.variants {
+                if !variants.exists() {
+                    panic!(
+                        "variants VCF {} for sample {} does not exist.",
+                        variants.display(),
+                        sample.name
+                    );
+                }
+                if sample
+                    .input_genome
+                    .extension()
+                    .is_some_and(|ext| ext == "npy")
+                {
+                    panic!(
+                        "Sample {} has variants, so its input_genome must be FASTA.",
+                        sample.name
+                    );
+                }
+            }
+            // squiggle files are already signal, so can't be modified
+            let is_squiggle = |path: &Path| path.extension().is_some_and(|ext| ext == "npy");
+            let has_squiggle = match sample.input_genome.read_dir() {
+                Ok(entries) => entries.flatten().any(|entry| is_squiggle(&entry.path())),
+                Err(_) => is_squiggle(&sample.input_genome),
+            };
+            if sample.has_modifications() && has_squiggle {
+                panic!(
+                    "Sample {} has modifications, so its input_genome must be FASTA, not squiggle.",
+                    sample.name
+                );
+            }
+            if sample.has_modifications() && self.modified_pore_model.is_none() {
+                panic!(
+                    "Sample {} has modifications, so a modified_pore_model must be set.",
+                    sample.name
+                );
+            }
+            // check every motif can be modified
+            sample.get_motif_modifications();
+            if !(0.0..1.0).contains(&sample.get_chimera_rate()) {
+                panic!(
+                    "chimera_rate for sample {} must be at least 0 and less than 1.",
+                    sample.name
+                );
             }
             if sample.is_amplicon() && sample.is_barcoded() {
                 if let Some(barcodes) = &sample.barcodes {
@@ -179,12 +549,263 @@ struct Parameters {
     device_id: String,
     position: String,
     break_read_ms: Option<u64>,
+    mux_scan_interval_minutes: Option<u64>,
 }
 
 impl Parameters {
     pub fn get_chunk_size_ms(&self) -> u64 {
         self.break_read_ms.unwrap_or(400)
     }
+
+    /// Get the number of seconds between mux scans, or None if mux scans are off. If not set, or set to 0, there are no
+    /// mux scans
+    pub fn get_mux_scan_interval_seconds(&self) -> Option<u64> {
+        match self.mux_scan_interval_minutes.unwrap_or(0) {
+            0 => None,
+            minutes => Some(minutes * 60),
+        }
+    }
+}
+
+/// Settings for the noise in simulated R10 signal
+#[derive(Deserialize, Debug, Clone, Default)]
+struct SignalConfig {
+    level_noise: Option<f64>,
+    dwell_distribution: Option<String>,
+    dwell_stdv: Option<f64>,
+    read_scale_stdv: Option<f64>,
+    read_shift_stdv: Option<f64>,
+    on_demand: Option<bool>,
+}
+
+impl SignalConfig {
+    /// Multiplier on the standard deviation of each kmer level. Default 0.0, noiseless
+    pub fn get_level_noise(&self) -> f64 {
+        self.level_noise.unwrap_or(0.0)
+    }
+
+    /// Standard deviation of the samples per base for gamma distributed dwell. Defaults to 40% of the mean samples per base
+    pub fn get_dwell_stdv(&self, samples_per_base: f64) -> f64 {
+        self.dwell_stdv.unwrap_or(samples_per_base * 0.4)
+    }
+
+    /// Check that we have a valid dwell distribution around the mean samples per base, or return the default fixed dwell.
+    pub fn get_dwell_distribution(&self, samples_per_base: f64) -> DwellDistribution {
+        match &self.dwell_distribution {
+            Some(distribution) => match distribution.to_lowercase().as_str() {
+                "fixed" => DwellDistribution::Fixed,
+                "gamma" => DwellDistribution::Gamma(self.get_dwell_stdv(samples_per_base)),
+                "exponential" => DwellDistribution::Exponential,
+                _ => {
+                    panic!("Invalid dwell distribution specified")
+                }
+            },
+            None => DwellDistribution::Fixed,
+        }
+    }
+
+    /// Standard deviation of the scale applied to each read's signal, around 1.0. Default 0.0
+    pub fn get_read_scale_stdv(&self) -> f64 {
+        self.read_scale_stdv.unwrap_or(0.0)
+    }
+
+    /// Standard deviation of the shift in picoamps applied to each read's signal, around 0.0. Default 0.0
+    pub fn get_read_shift_stdv(&self) -> f64 {
+        self.read_shift_stdv.unwrap_or(0.0)
+    }
+
+    /// Keep sequence two bit packed and simulate each read's signal as it is needed, rather than converting whole contigs to
+    /// signal on start up. Default false
+    pub fn get_on_demand(&self) -> bool {
+        self.on_demand.unwrap_or(false)
+    }
+}
+
+/// How each read is put together, from the read_structure section of the simulation profile
+#[derive(Deserialize, Debug, Clone, Default)]
+struct ReadStructureConfig {
+    start_adapter: Option<bool>,
+    end_adapter: Option<bool>,
+    middle_adapter: Option<bool>,
+    stall_ms: Option<f64>,
+    barcode_chance: Option<f64>,
+    truncation_chance: Option<f64>,
+    poly_a_length: Option<f64>,
+}
+
+impl ReadStructureConfig {
+    /// Whether reads start with adapter signal. Defaults to true for R10 and false for R9
+    pub fn get_start_adapter(&self, pore_type: &PoreType) -> bool {
+        self.start_adapter
+            .unwrap_or(matches!(pore_type, PoreType::R10))
+    }
+
+    /// Whether reads end with adapter signal. Defaults to true for barcoded R10 reads, which have always ended with
+    /// adapter to pad the second barcode, and false otherwise
+    pub fn get_end_adapter(&self, pore_type: &PoreType, is_barcoded: bool) -> bool {
+        self.end_adapter
+            .unwrap_or(is_barcoded && matches!(pore_type, PoreType::R10))
+    }
+
+    /// Whether there is adapter signal between the molecules of a chimeric read. Default false
+    pub fn get_middle_adapter(&self) -> bool {
+        self.middle_adapter.unwrap_or(false)
+    }
+
+    /// Mean length in milliseconds of the motor protein stall after the start adapter. Default 0.0
+    pub fn get_stall_ms(&self) -> f64 {
+        self.stall_ms.unwrap_or(0.0)
+    }
+
+    /// Chance each barcode of a barcoded read is present. Default 1.0
+    pub fn get_barcode_chance(&self) -> f64 {
+        self.barcode_chance.unwrap_or(1.0)
+    }
+
+    /// Chance a read ends early, part way through the sequence. Default 0.0
+    pub fn get_truncation_chance(&self) -> f64 {
+        self.truncation_chance.unwrap_or(0.0)
+    }
+
+    /// Mean length in bases of the poly(A) tail at the start of direct RNA reads. Default 100.0
+    pub fn get_poly_a_length(&self) -> f64 {
+        self.poly_a_length.unwrap_or(100.0)
+    }
+}
+
+/// Shortest fragment served as a short fragment junk event, in bases
+const MIN_SHORT_FRAGMENT_BASES: usize = 50;
+
+/// Events that aren't reads of the reference, from the junk_events section of the simulation profile. Each fraction is the
+/// fraction of all events that are of that kind.
+#[derive(Deserialize, Debug, Clone, Default)]
+struct JunkEventsConfig {
+    adapter_only_fraction: Option<f64>,
+    short_fragment_fraction: Option<f64>,
+    max_short_fragment_bases: Option<usize>,
+    noise_fraction: Option<f64>,
+}
+
+impl JunkEventsConfig {
+    /// Fraction of events that are adapter with no strand after it. Default 0.0
+    pub fn get_adapter_only_fraction(&self) -> f64 {
+        self.adapter_only_fraction.unwrap_or(0.0)
+    }
+
+    /// Fraction of events that are fragments too short to map. Default 0.0
+    pub fn get_short_fragment_fraction(&self) -> f64 {
+        self.short_fragment_fraction.unwrap_or(0.0)
+    }
+
+    /// Longest short fragment, in bases. Default 500
+    pub fn get_max_short_fragment_bases(&self) -> usize {
+        self.max_short_fragment_bases.unwrap_or(500)
+    }
+
+    /// Fraction of events that are noise, classified as zero, multiple or event. Default 0.0
+    pub fn get_noise_fraction(&self) -> f64 {
+        self.noise_fraction.unwrap_or(0.0)
+    }
+}
+
+/// How open pores capture molecules, from the capture section of the simulation profile
+#[derive(Deserialize, Debug, Clone, Default)]
+struct CaptureConfig {
+    mean_time_to_capture: Option<f64>,
+    concentration: Option<f64>,
+    reference_length: Option<f64>,
+    length_exponent: Option<f64>,
+    half_life_hours: Option<f64>,
+}
+
+impl CaptureConfig {
+    /// Mean seconds an open pore waits to capture a molecule of the reference length at a concentration of 1. Default 0.3
+    pub fn get_mean_time_to_capture(&self) -> f64 {
+        self.mean_time_to_capture.unwrap_or(0.3)
+    }
+
+    /// Concentration of the library, relative to the one the mean time to capture is for. Default 1.0
+    pub fn get_concentration(&self) -> f64 {
+        self.concentration.unwrap_or(1.0)
+    }
+
+    /// Length in bases the mean time to capture is for. Default 10000.0
+    pub fn get_reference_length(&self) -> f64 {
+        self.reference_length.unwrap_or(10000.0)
+    }
+
+    /// How much faster short molecules are captured, the capture rate going with (reference_length / length) to this power.
+    /// Default 0.0, capture doesn't depend on length
+    pub fn get_length_exponent(&self) -> f64 {
+        self.length_exponent.unwrap_or(0.0)
+    }
+
+    /// Build the capture model. The library is never used up unless half_life_hours is set.
+    pub fn get_capture_model(&self) -> CaptureModel {
+        CaptureModel::new(
+            self.get_mean_time_to_capture(),
+            self.get_concentration(),
+            self.get_reference_length(),
+            self.get_length_exponent(),
+            self.half_life_hours.map(|hours| hours * 3600.0),
+        )
+    }
+}
+
+/// How pores wear out and block, from the pore_lifetime section of the simulation profile
+#[derive(Deserialize, Debug, Clone, Default)]
+struct PoreLifetimeConfig {
+    pore_block_chance: Option<f64>,
+    half_life_hours: Option<f64>,
+    unblock_death_chance: Option<f64>,
+    block_timeout_seconds: Option<f64>,
+    permanent_block_bases: Option<f64>,
+}
+
+impl PoreLifetimeConfig {
+    /// Chance the pore blocks part way through any given read. Default 0.0
+    pub fn get_pore_block_chance(&self) -> f64 {
+        self.pore_block_chance.unwrap_or(0.0)
+    }
+
+    /// Seconds for half of the pores to die with time. If not set pores die at a rate that reaches the target yield
+    pub fn get_half_life_seconds(&self) -> Option<f64> {
+        self.half_life_hours.map(|hours| hours * 3600.0)
+    }
+
+    /// Chance each unblock, by a client of the data service or to clear a block, kills the pore. Default 0.0
+    pub fn get_unblock_death_chance(&self) -> f64 {
+        self.unblock_death_chance.unwrap_or(0.0)
+    }
+
+    /// Seconds a blocked pore stays blocked before MinKNOW unblocks it, if it isn't unblocked first. Default 0.0
+    pub fn get_block_timeout_seconds(&self) -> f64 {
+        self.block_timeout_seconds.unwrap_or(0.0)
+    }
+
+    /// Read length in bases at which a read is as likely as not to block the pore for good. Default unset, blocks are
+    /// never permanent
+    pub fn get_permanent_block_bases(&self) -> Option<f64> {
+        self.permanent_block_bases
+    }
+}
+
+/// Overrides for the run metadata written into the output files. Anything not set here is defaulted from the flow cell and kit.
+#[derive(Deserialize, Debug, Clone, Default)]
+struct RunMetadataConfig {
+    flow_cell_product_code: Option<String>,
+    sequencing_kit: Option<String>,
+    device_type: Option<String>,
+    host_product_code: Option<String>,
+    hostname: Option<String>,
+    operating_system: Option<String>,
+    basecall_config_filename: Option<String>,
+    guppy_version: Option<String>,
+    minknow_version: Option<String>,
+    /// Any other tracking_id fields, which take precedence over everything else
+    tracking_id: Option<HashMap<String, String>>,
+    /// Any other context_tags fields, which take precedence over everything else
+    context_tags: Option<HashMap<String, String>>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -198,17 +819,174 @@ struct Sample {
     barcodes: Option<Vec<String>>,
     barcode_weights: Option<Vec<usize>>,
     uneven: Option<bool>,
+    scheme_bed: Option<std::path::PathBuf>,
+    amplicon_dropout: Option<f64>,
+    partial_amplicon_chance: Option<f64>,
+    chimera_rate: Option<f64>,
+    variants: Option<std::path::PathBuf>,
+    modifications: Option<Vec<ModificationConfig>>,
+    bedmethyl: Option<std::path::PathBuf>,
+    read_length: Option<ReadLengthConfig>,
+    molecules: Option<usize>,
+    rejected_return_chance: Option<f64>,
+}
+
+/// The distribution the read lengths of a sample are drawn from, in bases
+#[derive(Deserialize, Debug, Clone, Default)]
+struct ReadLengthConfig {
+    distribution: Option<String>,
+    mean: Option<f64>,
+    stdv: Option<f64>,
+    shape: Option<f64>,
+    histogram: Option<std::path::PathBuf>,
+    lengths: Option<std::path::PathBuf>,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+impl ReadLengthConfig {
+    /// Name of the distribution - skew, lognormal, gamma, weibull, fixed, histogram or empirical. Default skew
+    pub fn get_distribution(&self) -> &str {
+        self.distribution.as_deref().unwrap_or("skew")
+    }
+
+    /// Shortest read in bases, longer reads are not clipped. Default 0.0
+    pub fn get_min(&self) -> f64 {
+        self.min.unwrap_or(0.0)
+    }
+
+    /// Longest read in bases, shorter reads are not clipped. Default unlimited
+    pub fn get_max(&self) -> f64 {
+        self.max.unwrap_or(f64::INFINITY)
+    }
+}
+
+/// A motif that is modified in a sample
+#[derive(Deserialize, Debug, Clone)]
+struct ModificationConfig {
+    motif: String,
+    code: String,
+    position: Option<usize>,
+    fraction: Option<f64>,
 }
 
 impl Sample {
-    pub fn get_read_len_dist(&self, global_read_len: Option<f64>) -> ReadLengthDist {
-        match self.mean_read_length {
-            Some(mrl) => ReadLengthDist::new(mrl / 450.0 * 4000.0),
-            None => ReadLengthDist::new(global_read_len.unwrap() / 450.0 * 4000.0),
+    /// Get the read length distribution settings of this sample, defaulting to a skewed distribution.
+    pub fn get_read_length(&self) -> ReadLengthConfig {
+        self.read_length.clone().unwrap_or_default()
+    }
+
+    /// Get the mean read length in bases, from the read length table, the sample's mean_read_length or the global mean
+    /// read length, in that order
+    pub fn get_mean_read_length(&self, global_read_len: Option<f64>) -> Option<f64> {
+        self.get_read_length()
+            .mean
+            .or(self.mean_read_length)
+            .or(global_read_len)
+    }
+
+    /// Get the distribution of read lengths in samples, drawn in bases and converted with `samples_per_base`. Panics if
+    /// the distribution is invalid.
+    pub fn get_read_len_dist(
+        &self,
+        global_read_len: Option<f64>,
+        samples_per_base: f64,
+    ) -> ReadLengthDist {
+        let read_length = self.get_read_length();
+        let mean = || {
+            self.get_mean_read_length(global_read_len)
+                .unwrap_or_else(|| {
+                    panic!(
+                    "Sample {} does not have a mean read length and no global read length is set.",
+                    self.name
+                )
+                })
+        };
+        let distribution = match read_length.get_distribution() {
+            "skew" => LengthDistribution::skew(mean(), read_length.stdv),
+            "lognormal" => LengthDistribution::log_normal(mean(), read_length.stdv),
+            "gamma" => LengthDistribution::gamma(mean(), read_length.stdv),
+            "weibull" => match read_length.shape {
+                Some(shape) => LengthDistribution::weibull(mean(), shape),
+                None => panic!("Sample {} has a weibull read length distribution but no shape.", self.name),
+            },
+            "fixed" => Ok(LengthDistribution::Fixed(mean())),
+            "empirical" => match &read_length.lengths {
+                Some(path) => EmpiricalLengths::from_file(path).map(LengthDistribution::Empirical),
+                None => panic!("Sample {} has an empirical read length distribution but no lengths file.", self.name),
+            },
+            "histogram" => match &read_length.histogram {
+                Some(path) => LengthDistribution::histogram(path),
+                None => panic!("Sample {} has a histogram read length distribution but no histogram file.", self.name),
+            },
+            other => panic!(
+                "Unknown read length distribution {other} for sample {}, expected one of skew, lognormal, gamma, weibull, fixed, histogram or empirical.",
+                self.name
+            ),
         }
+        .unwrap_or_else(|err| panic!("Invalid read length distribution for sample {} - {err}", self.name));
+        ReadLengthDist::new(
+            distribution,
+            read_length.get_min(),
+            read_length.get_max(),
+            samples_per_base,
+        )
     }
     pub fn is_amplicon(&self) -> bool {
-        self.amplicon.unwrap_or(false)
+        self.amplicon.unwrap_or(false) || self.scheme_bed.is_some()
+    }
+    /// Chance each amplicon of a primer scheme drops out for a barcode, and is never read. Default 0.0
+    pub fn get_amplicon_dropout(&self) -> f64 {
+        self.amplicon_dropout.unwrap_or(0.0)
+    }
+    /// Chance a read of a primer scheme amplicon stops part way through. Default 0.05
+    pub fn get_partial_amplicon_chance(&self) -> f64 {
+        self.partial_amplicon_chance.unwrap_or(0.05)
+    }
+    /// Chance a read from this sample carries on into another molecule, making it chimeric. Default 0.0
+    pub fn get_chimera_rate(&self) -> f64 {
+        self.chimera_rate.unwrap_or(0.0)
+    }
+    /// Chance a molecule of a finite library that is rejected by a client goes back into the library. Default 1.0
+    pub fn get_rejected_return_chance(&self) -> f64 {
+        self.rejected_return_chance.unwrap_or(1.0)
+    }
+    /// Whether any bases of this sample are modified, from motifs or a bedMethyl file
+    pub fn has_modifications(&self) -> bool {
+        self.modifications.is_some() || self.bedmethyl.is_some()
+    }
+    /// Get the modified motifs of this sample, panicking if any are invalid. The fraction of reads modified at each site
+    /// defaults to 1.0
+    pub fn get_motif_modifications(&self) -> Vec<modifications::MotifModification> {
+        self.modifications
+            .iter()
+            .flatten()
+            .map(|config| {
+                let modification = modifications::Modification::from_code(&config.code)
+                    .unwrap_or_else(|| {
+                        panic!(
+                            "Unknown modification code {} for sample {}",
+                            config.code, self.name
+                        )
+                    });
+                let fraction = config.fraction.unwrap_or(1.0);
+                if !(0.0..=1.0).contains(&fraction) {
+                    panic!(
+                        "Modification fraction for sample {} must be between 0 and 1.",
+                        self.name
+                    )
+                }
+                modifications::MotifModification::new(
+                    &config.motif,
+                    config.position,
+                    modification,
+                    fraction,
+                )
+                .unwrap_or_else(|err| {
+                    panic!("Invalid modification for sample {} - {err}", self.name)
+                })
+            })
+            .collect()
     }
     pub fn is_barcoded(&self) -> bool {
         self.barcodes.is_some()
@@ -232,10 +1010,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Parse the arguments from the command line
     let args = cli::Cli::parse();
     args.set_logging();
+    if let Some(cli::Command::Squiggle(squiggle_args)) = &args.command {
+        let config = args.simulation_profile.as_ref().map(_load_toml);
+        return squiggle::run(squiggle_args, config.as_ref());
+    }
     args.check_config_exists();
     // Parse the config to load all the samples
-    let config = _load_toml(&args.simulation_profile);
+    let config = _load_toml(args.get_simulation_profile());
     config.check_fields();
+    clock::start(config.get_speed_up());
 
     // Read the config.ini to get the TLS and ports and number of channels
     let mut software_config = Ini::new();
@@ -271,10 +1054,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let sample_id = config.parameters.sample_name.clone();
     let experiment_id = config.parameters.experiment_name.clone();
     let output_dir = config.output_path.clone();
-    let start_time_string: String = format!("{}", Utc::now().format("%Y%m%d_%H%M"));
+    let start_time_string: String = format!("{}", clock::now().format("%Y%m%d_%H%M"));
     let flowcell_id = config.parameters.flowcell_name.clone();
     let experiment_duration = config.parameters.experiment_duration_set;
-    let start_time = Utc::now();
+    let start_time = clock::now();
     let mut output_path = output_dir.clone();
     output_path.push(experiment_id);
     output_path.push(sample_id);
@@ -323,6 +1106,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let graceful_shutdown = Arc::new(Mutex::new(false));
     let graceful_shutdown_clone = Arc::clone(&graceful_shutdown);
+    let (write_out_done_tx, write_out_done_rx) = sync_channel(1);
     // Create the position server for our one position.
     let log_svc = LogServiceServer::new(Log {});
     let instance_svc = InstanceServiceServer::new(Instance {});
@@ -341,14 +1125,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         output_path.clone(),
         channel_size,
         graceful_shutdown_clone,
+        write_out_done_tx,
     ));
     ctrlc::set_handler(move || {
-        warn!("Caught keyboard interrupt, exiting in 10 seconds!");
+        warn!("Caught keyboard interrupt, writing out remaining reads before exiting!");
         {
             let mut x = graceful_shutdown.lock().unwrap();
             *x = true;
         }
-        std::thread::sleep(Duration::from_secs(10));
+        // wait for the write out thread to flush every pending read
+        match write_out_done_rx.recv() {
+            Ok(total_written) => info!("Wrote out {total_written} reads, exiting"),
+            Err(_) => error!("Write out thread stopped unexpectedly, exiting"),
+        }
         std::process::exit(0);
     })
     .expect("FAILED TO CATCH SIGNAL SOMWHOW");
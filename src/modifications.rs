@@ -0,0 +1,239 @@
+//! Base modifications - which bases of each read are modified, and how they are written in a modified kmer table.
+//!
+//! A sample can be modified at every match of a motif, such as the C of CG for CpG methylation or the A of GATC for Dam
+//! methylation, each read being modified at a site with the motif's fraction. It can also be modified at the sites of a
+//! bedMethyl file, such as the output of modkit, with the fraction of reads given for each site. Only sites on the + strand
+//! are used, as reads are simulated from the forward strand of the reference. Sites are found on each contig through the
+//! variants applied to its haplotype, and motifs are matched 5' to 3' even on direct RNA contigs, which are reversed.
+//!
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::read_to_string;
+use std::path::Path;
+
+use rand::Rng;
+
+use crate::variants::Shifts;
+
+/// A kind of modified base
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Modification {
+    /// 5-methylcytosine
+    FiveMethylC,
+    /// N6-methyladenine
+    SixMethylA,
+}
+
+impl Modification {
+    /// Get a modification from its code in MM tags and bedMethyl files, m or a, or its name, 5mC or 6mA.
+    pub fn from_code(code: &str) -> Option<Modification> {
+        match code {
+            "m" | "5mC" => Some(Modification::FiveMethylC),
+            "a" | "6mA" => Some(Modification::SixMethylA),
+            _ => None,
+        }
+    }
+
+    /// The code of the modification in MM tags and bedMethyl files.
+    pub fn code(&self) -> char {
+        match self {
+            Modification::FiveMethylC => 'm',
+            Modification::SixMethylA => 'a',
+        }
+    }
+
+    /// The letter the modified base is written as in a modified kmer table.
+    pub fn kmer_letter(&self) -> u8 {
+        match self {
+            Modification::FiveMethylC => b'M',
+            Modification::SixMethylA => b'Y',
+        }
+    }
+
+    /// The canonical base that is modified.
+    pub fn base(&self) -> u8 {
+        match self {
+            Modification::FiveMethylC => b'C',
+            Modification::SixMethylA => b'A',
+        }
+    }
+}
+
+/// Every letter that can stand for a modified base in a modified kmer table
+pub const KMER_LETTERS: [char; 2] = ['M', 'Y'];
+
+/// A motif that is modified wherever it appears
+#[derive(Debug, Clone)]
+pub struct MotifModification {
+    motif: Vec<u8>,
+    /// The position of the modified base in the motif
+    offset: usize,
+    modification: Modification,
+    /// Fraction of reads modified at each site
+    fraction: f64,
+}
+
+impl MotifModification {
+    /// Create a motif modification. The modified base defaults to the first base of the motif that the modification can be
+    /// on, e.g. the C of CG for 5mC.
+    pub fn new(
+        motif: &str,
+        offset: Option<usize>,
+        modification: Modification,
+        fraction: f64,
+    ) -> Result<MotifModification, Box<dyn Error>> {
+        let motif = motif.to_uppercase().into_bytes();
+        let offset = match offset {
+            Some(offset) => offset,
+            None => motif
+                .iter()
+                .position(|base| *base == modification.base())
+                .ok_or_else(|| {
+                    format!(
+                        "Motif {} has no {} to modify",
+                        String::from_utf8_lossy(&motif),
+                        modification.base() as char
+                    )
+                })?,
+        };
+        if motif.get(offset) != Some(&modification.base()) {
+            return Err(format!(
+                "Base {offset} of motif {} is not a {}",
+                String::from_utf8_lossy(&motif),
+                modification.base() as char
+            )
+            .into());
+        }
+        Ok(MotifModification {
+            motif,
+            offset,
+            modification,
+            fraction,
+        })
+    }
+}
+
+/// Where the bases of a contig simulated from sequence are on the reference, so the sites of a bedMethyl file can be found on
+/// it
+#[derive(Debug, Clone)]
+pub struct ReferenceSpan {
+    /// The reference sequence the contig comes from
+    pub chrom: String,
+    /// Start of the contig on its haplotype
+    pub offset: usize,
+    /// Length of the contig in bases
+    pub len: usize,
+    /// How the variants applied to the haplotype move its positions from the reference
+    pub shifts: Shifts,
+    /// Whether the contig is its haplotype reversed, as direct RNA is read 3' to 5'
+    pub reverse: bool,
+}
+
+impl ReferenceSpan {
+    /// Move a position on the contig back to the reference. None if it is past the end of the contig, or was put there by
+    /// a variant.
+    pub fn to_reference(&self, position: usize) -> Option<usize> {
+        if position >= self.len {
+            return None;
+        }
+        let position = match self.reverse {
+            true => self.offset + self.len - 1 - position,
+            false => self.offset + position,
+        };
+        self.shifts.to_reference(position)
+    }
+}
+
+/// A modified site from a bedMethyl file
+#[derive(Debug, Clone)]
+struct Site {
+    position: usize,
+    modification: Modification,
+    fraction: f64,
+}
+
+/// The modifications of a sample
+#[derive(Debug, Default)]
+pub struct SampleModifications {
+    motifs: Vec<MotifModification>,
+    /// Sites from a bedMethyl file, by contig and sorted by position
+    sites: HashMap<String, Vec<Site>>,
+}
+
+impl SampleModifications {
+    /// Create the modifications of a sample from its motifs, and the sites in a bedMethyl file if it has one.
+    pub fn new(
+        motifs: Vec<MotifModification>,
+        bedmethyl: Option<&Path>,
+    ) -> Result<SampleModifications, Box<dyn Error>> {
+        let mut sites: HashMap<String, Vec<Site>> = HashMap::new();
+        if let Some(bedmethyl) = bedmethyl {
+            for line in read_to_string(bedmethyl)?.lines() {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                if line.starts_with('#') || fields.len() < 11 || fields[5] == "-" {
+                    continue;
+                }
+                let modification = Modification::from_code(fields[3]).ok_or_else(|| {
+                    format!("Unknown modification code {} in bedMethyl", fields[3])
+                })?;
+                sites.entry(fields[0].to_string()).or_default().push(Site {
+                    position: fields[1].parse()?,
+                    modification,
+                    fraction: fields[10].parse::<f64>()? / 100.0,
+                });
+            }
+            for contig_sites in sites.values_mut() {
+                contig_sites.sort_by_key(|site| site.position);
+            }
+        }
+        Ok(SampleModifications { motifs, sites })
+    }
+
+    /// Choose which bases of a read are modified, each site being modified with its fraction. The read's bases start at
+    /// `start` on the contig. Returns the position in the read of each modified base, in order.
+    pub fn modified_bases<R: Rng>(
+        &self,
+        contig: &ReferenceSpan,
+        sequence: &[u8],
+        start: usize,
+        rng: &mut R,
+    ) -> Vec<(usize, Modification)> {
+        let mut modified = vec![];
+        // motifs are read 5' to 3', so turn reversed direct RNA back round to match them
+        let forward: Cow<[u8]> = match contig.reverse {
+            true => Cow::Owned(sequence.iter().rev().copied().collect()),
+            false => Cow::Borrowed(sequence),
+        };
+        let in_read = |position: usize| match contig.reverse {
+            true => sequence.len() - 1 - position,
+            false => position,
+        };
+        for motif in &self.motifs {
+            for (position, window) in forward.windows(motif.motif.len()).enumerate() {
+                if window == motif.motif.as_slice() && rng.gen_bool(motif.fraction) {
+                    modified.push((in_read(position + motif.offset), motif.modification));
+                }
+            }
+        }
+        if let Some(sites) = self.sites.get(&contig.chrom) {
+            for position in 0..sequence.len() {
+                let Some(reference) = contig.to_reference(start + position) else {
+                    continue;
+                };
+                let first = sites.partition_point(|site| site.position < reference);
+                for site in sites[first..]
+                    .iter()
+                    .take_while(|site| site.position == reference)
+                {
+                    if rng.gen_bool(site.fraction.clamp(0.0, 1.0)) {
+                        modified.push((position, site.modification));
+                    }
+                }
+            }
+        }
+        modified.sort_by_key(|(position, _)| *position);
+        modified.dedup_by_key(|(position, _)| *position);
+        modified
+    }
+}
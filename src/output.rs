@@ -0,0 +1,300 @@
+//! Writing finished reads out to disk.
+//!
+//! Reads that have finished "sequencing" are sent to the write out thread in `impl_services::data`, which batches them up and hands
+//! each batch to a [`ReadWriter`]. There is one writer per supported file format. All writers share the [`RunMetadata`] and the
+//! calibration constants in this module, so that a read looks the same whichever format it is written out in.
+//!
+pub mod fast5;
+pub mod pod5;
+pub mod slow5;
+
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+
+use chrono::prelude::*;
+
+use crate::kits;
+use crate::Config;
+
+/// Digitisation of the "ADC" - the number of discrete values the raw signal can take.
+pub const DIGITISATION: f64 = 8192.0;
+/// Offset added to the raw signal before scaling into picoamps.
+pub const CALIBRATION_OFFSET: f64 = 6.0;
+/// Range of the signal in picoamps covered by the digitisation.
+pub const CALIBRATION_RANGE: f64 = 1500.0;
+/// Median current of the open pore before each read, in picoamps.
+pub const MEDIAN_BEFORE: f64 = 100.0;
+
+/// The scale to convert a raw signal value into picoamps, pA = (raw + offset) * scale.
+pub fn calibration_scale() -> f64 {
+    CALIBRATION_RANGE / DIGITISATION
+}
+
+/// Convert a current in picoamps into a raw signal value, so that it reads back as the same current with the calibration
+/// written into every output file.
+pub fn picoamps_to_raw(picoamps: f64) -> i16 {
+    (picoamps / calibration_scale() - CALIBRATION_OFFSET) as i16
+}
+
+/// Convert a raw signal value into picoamps, with the calibration written into every output file.
+pub fn raw_to_picoamps(raw: i16) -> f64 {
+    (raw as f64 + CALIBRATION_OFFSET) * calibration_scale()
+}
+
+/// Run level metadata that is written into every output file - the FAST5 `tracking_id` and `context_tags` groups, and the
+/// POD5 run info table.
+#[derive(Debug, Clone)]
+pub struct RunMetadata {
+    /// The run id for this simulation
+    pub run_id: String,
+    /// The flowcell name from the simulation profile, used to name the output files
+    pub flowcell_name: String,
+    /// The position name from the simulation profile
+    pub position: String,
+    /// The time the experiment started
+    pub exp_start_time: DateTime<Utc>,
+    /// Sample rate, in Hz, that the signal is written out at
+    pub sample_rate: f64,
+    /// Key value pairs for the tracking_id
+    pub tracking_id: HashMap<String, String>,
+    /// Key value pairs for the context tags
+    pub context_tags: HashMap<String, String>,
+}
+
+impl RunMetadata {
+    /// Create the run metadata from the simulation profile. Anything not set in the `run_metadata` section of the profile is
+    /// defaulted from the flow cell and sequencing kit, which themselves default to match the pore type.
+    pub fn new(run_id: &str, config: &Config, exp_start_time: DateTime<Utc>) -> RunMetadata {
+        let settings = config.run_metadata.clone().unwrap_or_default();
+        let pore_type = config.check_pore_type();
+        let flow_cell = settings
+            .flow_cell_product_code
+            .unwrap_or_else(|| match config.get_rna_kit() {
+                Some(rna_kit) => rna_kit.flow_cell.to_string(),
+                None => kits::default_flow_cell(&pore_type).to_string(),
+            })
+            .to_uppercase();
+        let chemistry = kits::flow_cell_chemistry(&flow_cell)
+            .unwrap_or_else(|| kits::pore_type_chemistry(&pore_type));
+        let kit = settings
+            .sequencing_kit
+            .unwrap_or_else(|| chemistry.default_kit.to_string())
+            .to_uppercase();
+        let is_rna = kits::is_rna_kit(&kit);
+        let sample_rate = config.get_sample_rate();
+        let basecall_config = match settings.basecall_config_filename {
+            Some(basecall_config) => basecall_config,
+            None if is_rna => chemistry
+                .rna_basecall_config
+                .unwrap_or_else(|| chemistry.dna_basecall_config(sample_rate))
+                .to_string(),
+            None => chemistry.dna_basecall_config(sample_rate).to_string(),
+        };
+        let device_type = settings
+            .device_type
+            .unwrap_or_else(|| kits::device_type(&flow_cell).to_string());
+        let minknow_version = settings
+            .minknow_version
+            .unwrap_or_else(|| chemistry.minknow_version.to_string());
+        let barcoding_enabled = match config.sample.iter().any(|sample| sample.is_barcoded()) {
+            true => "1",
+            false => "0",
+        };
+
+        let iso_time = exp_start_time.to_rfc3339_opts(SecondsFormat::Millis, false);
+        let experiment_duration = config.get_experiment_duration_set().to_string();
+        let sample_frequency = (sample_rate as u32).to_string();
+        let kit_lowercase = kit.to_lowercase();
+        let exp_script_name = format!(
+            "sequencing/sequencing_{}_{},{},{}",
+            flow_cell.trim_start_matches("FLO-"),
+            if is_rna { "RNA" } else { "DNA" },
+            flow_cell,
+            kit
+        );
+        let context_tags = [
+            ("barcoding_enabled", barcoding_enabled),
+            ("basecall_config_filename", basecall_config.as_str()),
+            ("experiment_duration_set", experiment_duration.as_str()),
+            (
+                "experiment_type",
+                if is_rna { "rna" } else { "genomic_dna" },
+            ),
+            ("local_basecalling", "0"),
+            ("package", "bream4"),
+            ("package_version", chemistry.protocols_version),
+            ("sample_frequency", sample_frequency.as_str()),
+            ("sequencing_kit", kit_lowercase.as_str()),
+        ];
+        let tracking_id = [
+            ("asic_id", "817405089"),
+            ("asic_id_eeprom", "5661715"),
+            ("asic_temp", "29.357218"),
+            ("asic_version", "IA02D"),
+            ("auto_update", "0"),
+            (
+                "auto_update_source",
+                "https,//mirror.oxfordnanoportal.com/software/MinKNOW/",
+            ),
+            ("bream_is_standard", "0"),
+            ("configuration_version", chemistry.configuration_version),
+            ("device_id", config.parameters.device_id.as_str()),
+            ("device_type", device_type.as_str()),
+            ("distribution_status", "stable"),
+            ("distribution_version", chemistry.distribution_version),
+            ("exp_script_name", exp_script_name.as_str()),
+            ("exp_script_purpose", "sequencing_run"),
+            ("exp_start_time", iso_time.as_str()),
+            ("flow_cell_id", config.parameters.flowcell_name.as_str()),
+            ("flow_cell_product_code", flow_cell.as_str()),
+            (
+                "guppy_version",
+                settings
+                    .guppy_version
+                    .as_deref()
+                    .unwrap_or(chemistry.guppy_version),
+            ),
+            ("heatsink_temp", "34.066406"),
+            (
+                "host_product_code",
+                settings
+                    .host_product_code
+                    .as_deref()
+                    .unwrap_or("GRD-X5B003"),
+            ),
+            ("host_product_serial_number", "NOTFOUND"),
+            ("hostname", settings.hostname.as_deref().unwrap_or("master")),
+            ("installation_type", "nc"),
+            ("local_firmware_file", "1"),
+            (
+                "operating_system",
+                settings
+                    .operating_system
+                    .as_deref()
+                    .unwrap_or("ubuntu 20.04"),
+            ),
+            (
+                "protocol_group_id",
+                config.parameters.experiment_name.as_str(),
+            ),
+            ("protocol_run_id", "SYNTHETIC_RUN"),
+            ("protocol_start_time", iso_time.as_str()),
+            ("protocols_version", chemistry.protocols_version),
+            ("run_id", run_id),
+            ("sample_id", config.parameters.sample_name.as_str()),
+            ("usb_config", "fx3_1.2.4#fpga_1.2.1#bulk#USB300"),
+            ("version", minknow_version.as_str()),
+        ];
+        let mut tracking_id = to_owned_map(&tracking_id);
+        tracking_id.extend(settings.tracking_id.unwrap_or_default());
+        let mut context_tags = to_owned_map(&context_tags);
+        context_tags.extend(settings.context_tags.unwrap_or_default());
+        RunMetadata {
+            run_id: run_id.to_string(),
+            flowcell_name: config.parameters.flowcell_name.clone(),
+            position: config.parameters.position.clone(),
+            exp_start_time,
+            sample_rate,
+            tracking_id,
+            context_tags,
+        }
+    }
+
+    /// Get a tracking id value, or an empty string if it isn't set.
+    pub fn tracking(&self, key: &str) -> &str {
+        self.tracking_id.get(key).map_or("", |x| x.as_str())
+    }
+
+    /// Get a context tag value, or an empty string if it isn't set.
+    pub fn context(&self, key: &str) -> &str {
+        self.context_tags.get(key).map_or("", |x| x.as_str())
+    }
+}
+
+fn to_owned_map(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+    pairs
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+/// Why a read ended, as recorded by MinKNOW.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EndReason {
+    /// We don't know why the read ended
+    Unknown,
+    /// The read was cut short by a mux scan
+    MuxChange,
+    /// The pore became blocked, and MinKNOW reversed the voltage to clear it
+    UnblockMuxChange,
+    /// The read was unblocked by a client of the data service, such as readfish
+    DataServiceUnblockMuxChange,
+    /// The read ended normally, with the strand leaving the pore
+    SignalPositive,
+    /// The signal dropped out, here because the pore died part way through the read
+    SignalNegative,
+    /// The run was stopped while the read was being sequenced
+    ApiRequest,
+}
+
+impl EndReason {
+    /// The value of the FAST5 `end_reason` enum, which is also the index of the label in the SLOW5 header.
+    pub fn fast5_value(&self) -> u8 {
+        match self {
+            EndReason::Unknown => 0,
+            EndReason::MuxChange => 2,
+            EndReason::UnblockMuxChange => 3,
+            EndReason::DataServiceUnblockMuxChange => 4,
+            EndReason::SignalPositive => 5,
+            EndReason::SignalNegative => 6,
+            EndReason::ApiRequest => 7,
+        }
+    }
+
+    /// The name of the end reason, as used by POD5 and the sequencing summary.
+    pub fn name(&self) -> &'static str {
+        match self {
+            EndReason::Unknown => "unknown",
+            EndReason::MuxChange => "mux_change",
+            EndReason::UnblockMuxChange => "unblock_mux_change",
+            EndReason::DataServiceUnblockMuxChange => "data_service_unblock_mux_change",
+            EndReason::SignalPositive => "signal_positive",
+            EndReason::SignalNegative => "signal_negative",
+            EndReason::ApiRequest => "api_request",
+        }
+    }
+
+    /// Whether the read was ended by something acting on the pore, rather than the strand itself.
+    pub fn is_forced(&self) -> bool {
+        !matches!(
+            self,
+            EndReason::Unknown | EndReason::SignalPositive | EndReason::SignalNegative
+        )
+    }
+}
+
+/// A read that has finished sequencing and is ready to be written out.
+#[derive(Debug, Clone)]
+pub struct FinishedRead {
+    /// UUID of the read
+    pub read_id: String,
+    /// The number of the read on this run
+    pub read_number: u32,
+    /// The channel the read was sequenced on, 1 indexed
+    pub channel: usize,
+    /// The mux (well) the read was sequenced on
+    pub start_mux: u8,
+    /// Start time of the read in samples since the start of the experiment
+    pub start_time: u64,
+    /// Why the read ended
+    pub end_reason: EndReason,
+    /// The raw signal of the read, already truncated if the read was unblocked
+    pub signal: Vec<i16>,
+}
+
+/// Something that can write batches of finished reads out into files.
+pub trait ReadWriter {
+    /// Write a batch of finished reads into a new file, returning the path of the file that was written.
+    fn write_reads(&mut self, reads: &[FinishedRead]) -> io::Result<PathBuf>;
+}
@@ -0,0 +1,94 @@
+//! Writes finished reads into multi-read FAST5 files, using the VBZ compression plugin provided by ONT.
+use std::collections::HashMap;
+use std::fs::create_dir_all;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use frust5_api::*;
+
+use crate::output::{
+    FinishedRead, ReadWriter, RunMetadata, CALIBRATION_OFFSET, CALIBRATION_RANGE, DIGITISATION,
+    MEDIAN_BEFORE,
+};
+
+/// Writes each batch of reads into a new FAST5 file in `fast5_pass/`.
+pub struct Fast5Writer {
+    output_dir: PathBuf,
+    metadata: RunMetadata,
+    file_counter: usize,
+}
+
+impl Fast5Writer {
+    /// Create a new writer, creating the `fast5_pass` directory inside of the output path if it doesn't already exist.
+    pub fn new(output_path: &Path, metadata: RunMetadata) -> io::Result<Fast5Writer> {
+        let output_dir = output_path.join("fast5_pass");
+        create_dir_all(&output_dir)?;
+        Ok(Fast5Writer {
+            output_dir,
+            metadata,
+            file_counter: 0,
+        })
+    }
+}
+
+impl ReadWriter for Fast5Writer {
+    fn write_reads(&mut self, reads: &[FinishedRead]) -> io::Result<PathBuf> {
+        let fast5_file_name = self.output_dir.join(format!(
+            "{}_pass_{}_{}.fast5",
+            self.metadata.flowcell_name,
+            &self.metadata.run_id[0..6],
+            self.file_counter
+        ));
+        let tracking_id: HashMap<&str, &str> = self
+            .metadata
+            .tracking_id
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        let context_tags: HashMap<&str, &str> = self
+            .metadata
+            .context_tags
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        let mut multi =
+            MultiFast5File::new(fast5_file_name.display().to_string(), OpenMode::Append);
+        for read in reads {
+            let raw_attrs: HashMap<&str, RawAttrsOpts> = HashMap::from([
+                ("duration", RawAttrsOpts::Duration(read.signal.len() as u32)),
+                (
+                    "end_reason",
+                    RawAttrsOpts::EndReason(read.end_reason.fast5_value()),
+                ),
+                ("median_before", RawAttrsOpts::MedianBefore(MEDIAN_BEFORE)),
+                ("read_id", RawAttrsOpts::ReadId(read.read_id.as_str())),
+                (
+                    "read_number",
+                    RawAttrsOpts::ReadNumber(read.read_number as i32),
+                ),
+                ("start_mux", RawAttrsOpts::StartMux(read.start_mux)),
+                ("start_time", RawAttrsOpts::StartTime(read.start_time)),
+            ]);
+            let channel_info = ChannelInfo::new(
+                DIGITISATION,
+                CALIBRATION_OFFSET,
+                CALIBRATION_RANGE,
+                self.metadata.sample_rate,
+                read.channel.to_string(),
+            );
+            multi
+                .create_empty_read(
+                    read.read_id.clone(),
+                    self.metadata.run_id.clone(),
+                    &tracking_id,
+                    &context_tags,
+                    channel_info,
+                    &raw_attrs,
+                    read.signal.clone(),
+                )
+                .map_err(|err| io::Error::other(err.to_string()))?;
+        }
+        self.file_counter += 1;
+        Ok(fast5_file_name)
+    }
+}
@@ -0,0 +1,437 @@
+//! Writes finished reads into POD5 files.
+//!
+//! A POD5 file is a thin container around three Arrow IPC (Feather V2) files - the signal table, the run info table and the reads
+//! table - followed by a flatbuffer footer which records where each of them is in the file. The layout is described in
+//! <https://github.com/nanoporetech/pod5-file-format/blob/master/docs/SPECIFICATION.md>.
+//!
+//! Signal is written uncompressed, so neither writing nor reading these files needs the VBZ plugin.
+//!
+//! `python/check_output.py` checks files written here open with ONT's `pod5` reader and read back as they were written.
+use std::collections::HashMap;
+use std::fs::{create_dir_all, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use arrow_array::builder::{
+    FixedSizeBinaryBuilder, Int16Builder, LargeListBuilder, ListBuilder, MapBuilder, MapFieldNames,
+    StringBuilder, StringDictionaryBuilder, UInt64Builder,
+};
+use arrow_array::types::Int16Type;
+use arrow_array::{
+    Array, ArrayRef, BooleanArray, Float32Array, Int16Array, RecordBatch, StringArray,
+    TimestampMillisecondArray, UInt16Array, UInt32Array, UInt64Array, UInt8Array,
+};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{ArrowError, DataType, Field, Schema};
+use flatbuffers::FlatBufferBuilder;
+use uuid::Uuid;
+
+use crate::output::{
+    calibration_scale, FinishedRead, ReadWriter, RunMetadata, CALIBRATION_OFFSET, DIGITISATION,
+    MEDIAN_BEFORE,
+};
+
+/// Magic bytes at the start and end of every POD5 file
+const SIGNATURE: [u8; 8] = [0x8B, b'P', b'O', b'D', b'\r', b'\n', 0x1A, b'\n'];
+/// Marks the start of the footer
+const FOOTER_MAGIC: [u8; 8] = *b"FOOTER\0\0";
+/// The version of the POD5 specification that the tables are written to
+const POD5_VERSION: &str = "0.3.10";
+/// Written into the file as the software that created it
+const SOFTWARE: &str = "Icarust";
+/// Signal for a read is split into rows of at most this many samples in the signal table
+const MAX_SIGNAL_CHUNK_SIZE: usize = 102_400;
+/// The `Format` enum in the footer schema - all embedded files are Feather V2
+const FORMAT_FEATHER_V2: i16 = 0;
+/// The `ContentType` enum in the footer schema
+const CONTENT_READS_TABLE: i16 = 0;
+const CONTENT_SIGNAL_TABLE: i16 = 1;
+const CONTENT_RUN_INFO_TABLE: i16 = 4;
+
+/// Writes each batch of reads into a new POD5 file in `pod5_pass/`.
+pub struct Pod5Writer {
+    output_dir: PathBuf,
+    metadata: RunMetadata,
+    file_counter: usize,
+}
+
+impl Pod5Writer {
+    /// Create a new writer, creating the `pod5_pass` directory inside of the output path if it doesn't already exist.
+    pub fn new(output_path: &Path, metadata: RunMetadata) -> io::Result<Pod5Writer> {
+        let output_dir = output_path.join("pod5_pass");
+        create_dir_all(&output_dir)?;
+        Ok(Pod5Writer {
+            output_dir,
+            metadata,
+            file_counter: 0,
+        })
+    }
+}
+
+impl ReadWriter for Pod5Writer {
+    fn write_reads(&mut self, reads: &[FinishedRead]) -> io::Result<PathBuf> {
+        let pod5_file_name = self.output_dir.join(format!(
+            "{}_pass_{}_{}.pod5",
+            self.metadata.flowcell_name,
+            &self.metadata.run_id[0..6],
+            self.file_counter
+        ));
+        let file_identifier = Uuid::new_v4();
+        let schema_metadata = HashMap::from([
+            (
+                "MINKNOW:file_identifier".to_string(),
+                file_identifier.to_string(),
+            ),
+            ("MINKNOW:software".to_string(), SOFTWARE.to_string()),
+            ("MINKNOW:pod5_version".to_string(), POD5_VERSION.to_string()),
+        ]);
+        let (signal_table, signal_rows) = signal_table(reads, &schema_metadata)?;
+        let run_info_table = run_info_table(&self.metadata, &schema_metadata)?;
+        let reads_table = reads_table(reads, &signal_rows, &self.metadata, &schema_metadata)?;
+
+        let mut file = BufWriter::new(File::create(&pod5_file_name)?);
+        let section_marker = file_identifier.as_bytes();
+        file.write_all(&SIGNATURE)?;
+        file.write_all(section_marker)?;
+        let mut position = (SIGNATURE.len() + section_marker.len()) as i64;
+        let mut contents = Vec::with_capacity(3);
+        for (table, content_type) in [
+            (signal_table, CONTENT_SIGNAL_TABLE),
+            (run_info_table, CONTENT_RUN_INFO_TABLE),
+            (reads_table, CONTENT_READS_TABLE),
+        ] {
+            contents.push((position, table.len() as i64, content_type));
+            file.write_all(&table)?;
+            let padding = padding_to_eight(table.len());
+            file.write_all(&vec![0; padding])?;
+            file.write_all(section_marker)?;
+            position += (table.len() + padding + section_marker.len()) as i64;
+        }
+        let mut footer = footer(&file_identifier.to_string(), &contents);
+        footer.resize(footer.len() + padding_to_eight(footer.len()), 0);
+        file.write_all(&FOOTER_MAGIC)?;
+        file.write_all(&footer)?;
+        file.write_all(&(footer.len() as i64).to_le_bytes())?;
+        file.write_all(section_marker)?;
+        file.write_all(&SIGNATURE)?;
+        file.flush()?;
+        self.file_counter += 1;
+        Ok(pod5_file_name)
+    }
+}
+
+/// Number of zero bytes needed to pad a section of the given length to an eight byte boundary.
+fn padding_to_eight(len: usize) -> usize {
+    (8 - len % 8) % 8
+}
+
+fn arrow_to_io_error(err: ArrowError) -> io::Error {
+    io::Error::other(err.to_string())
+}
+
+/// Read ids are stored as the 16 raw bytes of the UUID, tagged as a `minknow.uuid` extension type.
+fn read_id_field() -> Field {
+    Field::new("read_id", DataType::FixedSizeBinary(16), false).with_metadata(HashMap::from([
+        (
+            "ARROW:extension:name".to_string(),
+            "minknow.uuid".to_string(),
+        ),
+        ("ARROW:extension:metadata".to_string(), String::new()),
+    ]))
+}
+
+fn read_id_column(reads: &[&str]) -> io::Result<ArrayRef> {
+    let mut builder = FixedSizeBinaryBuilder::with_capacity(reads.len(), 16);
+    for read_id in reads {
+        let uuid = Uuid::parse_str(read_id)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        builder
+            .append_value(uuid.as_bytes())
+            .map_err(arrow_to_io_error)?;
+    }
+    Ok(Arc::new(builder.finish()))
+}
+
+/// Write a single record batch out as an in memory Arrow IPC file.
+fn write_table(
+    fields: Vec<Field>,
+    columns: Vec<ArrayRef>,
+    schema_metadata: &HashMap<String, String>,
+) -> io::Result<Vec<u8>> {
+    let schema = Arc::new(Schema::new_with_metadata(fields, schema_metadata.clone()));
+    let batch = RecordBatch::try_new(schema.clone(), columns).map_err(arrow_to_io_error)?;
+    let mut writer = FileWriter::try_new(Vec::new(), &schema).map_err(arrow_to_io_error)?;
+    writer.write(&batch).map_err(arrow_to_io_error)?;
+    writer.into_inner().map_err(arrow_to_io_error)
+}
+
+/// Create the signal table, splitting each reads signal into chunks of at most `MAX_SIGNAL_CHUNK_SIZE`.
+/// Also returns the rows of the signal table that belong to each read, in the order of the reads.
+fn signal_table(
+    reads: &[FinishedRead],
+    schema_metadata: &HashMap<String, String>,
+) -> io::Result<(Vec<u8>, Vec<Vec<u64>>)> {
+    let mut read_ids = vec![];
+    let mut signal = LargeListBuilder::new(Int16Builder::new());
+    let mut samples = vec![];
+    let mut signal_rows = Vec::with_capacity(reads.len());
+    for read in reads {
+        let mut rows = vec![];
+        for chunk in read.signal.chunks(MAX_SIGNAL_CHUNK_SIZE) {
+            rows.push(read_ids.len() as u64);
+            read_ids.push(read.read_id.as_str());
+            signal.values().append_slice(chunk);
+            signal.append(true);
+            samples.push(chunk.len() as u32);
+        }
+        signal_rows.push(rows);
+    }
+    let signal = signal.finish();
+    let fields = vec![
+        read_id_field(),
+        Field::new("signal", signal.data_type().clone(), false),
+        Field::new("samples", DataType::UInt32, false),
+    ];
+    let columns: Vec<ArrayRef> = vec![
+        read_id_column(&read_ids)?,
+        Arc::new(signal),
+        Arc::new(UInt32Array::from(samples)),
+    ];
+    Ok((write_table(fields, columns, schema_metadata)?, signal_rows))
+}
+
+fn string_map_column(map: &HashMap<String, String>) -> ArrayRef {
+    let field_names = MapFieldNames {
+        entry: "entries".to_string(),
+        key: "key".to_string(),
+        value: "value".to_string(),
+    };
+    let mut builder = MapBuilder::new(
+        Some(field_names),
+        StringBuilder::new(),
+        StringBuilder::new(),
+    );
+    let mut entries: Vec<(&String, &String)> = map.iter().collect();
+    entries.sort();
+    for (key, value) in entries {
+        builder.keys().append_value(key);
+        builder.values().append_value(value);
+    }
+    builder.append(true).unwrap();
+    Arc::new(builder.finish())
+}
+
+/// Create the run info table, which has one row describing the simulated run.
+fn run_info_table(
+    metadata: &RunMetadata,
+    schema_metadata: &HashMap<String, String>,
+) -> io::Result<Vec<u8>> {
+    let start_time = metadata.exp_start_time.timestamp_millis();
+    let adc_max = (DIGITISATION / 2.0) as i16 - 1;
+    let string_column = |value: &str| -> ArrayRef { Arc::new(StringArray::from(vec![value])) };
+    let timestamp_column = || -> ArrayRef {
+        Arc::new(TimestampMillisecondArray::from(vec![start_time]).with_timezone("UTC"))
+    };
+    let columns: Vec<(&str, ArrayRef)> = vec![
+        ("acquisition_id", string_column(&metadata.run_id)),
+        ("acquisition_start_time", timestamp_column()),
+        ("adc_max", Arc::new(Int16Array::from(vec![adc_max]))),
+        ("adc_min", Arc::new(Int16Array::from(vec![-adc_max - 1]))),
+        ("context_tags", string_map_column(&metadata.context_tags)),
+        (
+            "experiment_name",
+            string_column(metadata.tracking("protocol_group_id")),
+        ),
+        (
+            "flow_cell_id",
+            string_column(metadata.tracking("flow_cell_id")),
+        ),
+        (
+            "flow_cell_product_code",
+            string_column(metadata.tracking("flow_cell_product_code")),
+        ),
+        (
+            "protocol_name",
+            string_column(metadata.tracking("exp_script_name")),
+        ),
+        (
+            "protocol_run_id",
+            string_column(metadata.tracking("protocol_run_id")),
+        ),
+        ("protocol_start_time", timestamp_column()),
+        ("sample_id", string_column(metadata.tracking("sample_id"))),
+        (
+            "sample_rate",
+            Arc::new(UInt16Array::from(vec![metadata.sample_rate as u16])),
+        ),
+        (
+            "sequencing_kit",
+            string_column(metadata.context("sequencing_kit")),
+        ),
+        ("sequencer_position", string_column(&metadata.position)),
+        (
+            "sequencer_position_type",
+            string_column(metadata.tracking("device_type")),
+        ),
+        ("software", string_column(SOFTWARE)),
+        ("system_name", string_column(metadata.tracking("hostname"))),
+        (
+            "system_type",
+            string_column(metadata.tracking("host_product_code")),
+        ),
+        ("tracking_id", string_map_column(&metadata.tracking_id)),
+    ];
+    let fields = columns
+        .iter()
+        .map(|(name, column)| Field::new(*name, column.data_type().clone(), false))
+        .collect();
+    write_table(
+        fields,
+        columns.into_iter().map(|(_, column)| column).collect(),
+        schema_metadata,
+    )
+}
+
+/// Create the reads table, which has one row per read pointing at the rows of the signal table containing its signal.
+fn reads_table(
+    reads: &[FinishedRead],
+    signal_rows: &[Vec<u64>],
+    metadata: &RunMetadata,
+    schema_metadata: &HashMap<String, String>,
+) -> io::Result<Vec<u8>> {
+    let read_ids: Vec<&str> = reads.iter().map(|read| read.read_id.as_str()).collect();
+    let mut signal = ListBuilder::new(UInt64Builder::new());
+    for rows in signal_rows {
+        signal.values().append_slice(rows);
+        signal.append(true);
+    }
+    let mut pore_type = StringDictionaryBuilder::<Int16Type>::new();
+    let mut end_reason = StringDictionaryBuilder::<Int16Type>::new();
+    let mut run_info = StringDictionaryBuilder::<Int16Type>::new();
+    for read in reads {
+        pore_type.append_value("not_set");
+        end_reason.append_value(read.end_reason.name());
+        run_info.append_value(&metadata.run_id);
+    }
+    let float_column =
+        |value: f32| -> ArrayRef { Arc::new(Float32Array::from(vec![value; reads.len()])) };
+    let columns: Vec<(&str, ArrayRef)> = vec![
+        ("read_id", read_id_column(&read_ids)?),
+        ("signal", Arc::new(signal.finish())),
+        (
+            "read_number",
+            Arc::new(UInt32Array::from_iter_values(
+                reads.iter().map(|read| read.read_number),
+            )),
+        ),
+        (
+            "start",
+            Arc::new(UInt64Array::from_iter_values(
+                reads.iter().map(|read| read.start_time),
+            )),
+        ),
+        ("median_before", float_column(MEDIAN_BEFORE as f32)),
+        (
+            "num_minknow_events",
+            Arc::new(UInt64Array::from(vec![0; reads.len()])),
+        ),
+        ("tracked_scaling_scale", float_column(f32::NAN)),
+        ("tracked_scaling_shift", float_column(f32::NAN)),
+        ("predicted_scaling_scale", float_column(f32::NAN)),
+        ("predicted_scaling_shift", float_column(f32::NAN)),
+        (
+            "num_reads_since_mux_change",
+            Arc::new(UInt32Array::from(vec![0; reads.len()])),
+        ),
+        ("time_since_mux_change", float_column(0.0)),
+        (
+            "channel",
+            Arc::new(UInt16Array::from_iter_values(
+                reads.iter().map(|read| read.channel as u16),
+            )),
+        ),
+        (
+            "well",
+            Arc::new(UInt8Array::from_iter_values(
+                reads.iter().map(|read| read.start_mux),
+            )),
+        ),
+        ("pore_type", Arc::new(pore_type.finish())),
+        (
+            "calibration_offset",
+            float_column(CALIBRATION_OFFSET as f32),
+        ),
+        (
+            "calibration_scale",
+            float_column(calibration_scale() as f32),
+        ),
+        ("end_reason", Arc::new(end_reason.finish())),
+        (
+            "end_reason_forced",
+            Arc::new(BooleanArray::from_iter(
+                reads.iter().map(|read| Some(read.end_reason.is_forced())),
+            )),
+        ),
+        ("run_info", Arc::new(run_info.finish())),
+        (
+            "num_samples",
+            Arc::new(UInt64Array::from_iter_values(
+                reads.iter().map(|read| read.signal.len() as u64),
+            )),
+        ),
+        ("open_pore_level", float_column(f32::NAN)),
+    ];
+    let mut dict_id = 0;
+    let fields = columns
+        .iter()
+        .map(|(name, column)| match column.data_type() {
+            // Each dictionary column needs its own id, or they would overwrite each others dictionaries in the file
+            DataType::Dictionary(_, _) => {
+                dict_id += 1;
+                Field::new_dict(*name, column.data_type().clone(), false, dict_id, false)
+            }
+            _ if *name == "read_id" => read_id_field(),
+            _ => Field::new(*name, column.data_type().clone(), false),
+        })
+        .collect();
+    write_table(
+        fields,
+        columns.into_iter().map(|(_, column)| column).collect(),
+        schema_metadata,
+    )
+}
+
+/// Build the footer flatbuffer, recording the position, length and content type of each embedded table.
+///
+/// The footer schema is
+/// ```text
+/// table EmbeddedFile { offset: long; length: long; format: Format (short); content_type: ContentType (short); }
+/// table Footer { file_identifier: string; software: string; pod5_version: string; contents: [EmbeddedFile]; }
+/// ```
+fn footer(file_identifier: &str, contents: &[(i64, i64, i16)]) -> Vec<u8> {
+    let mut fbb = FlatBufferBuilder::new();
+    let embedded_files: Vec<_> = contents
+        .iter()
+        .map(|(offset, length, content_type)| {
+            let start = fbb.start_table();
+            fbb.push_slot::<i64>(4, *offset, 0);
+            fbb.push_slot::<i64>(6, *length, 0);
+            fbb.push_slot::<i16>(8, FORMAT_FEATHER_V2, 0);
+            fbb.push_slot::<i16>(10, *content_type, 0);
+            fbb.end_table(start)
+        })
+        .collect();
+    let contents = fbb.create_vector(&embedded_files);
+    let file_identifier = fbb.create_string(file_identifier);
+    let software = fbb.create_string(SOFTWARE);
+    let pod5_version = fbb.create_string(POD5_VERSION);
+    let start = fbb.start_table();
+    fbb.push_slot_always(4, file_identifier);
+    fbb.push_slot_always(6, software);
+    fbb.push_slot_always(8, pod5_version);
+    fbb.push_slot_always(10, contents);
+    let root = fbb.end_table(start);
+    fbb.finish(root, None);
+    fbb.finished_data().to_vec()
+}
@@ -0,0 +1,228 @@
+//! Writes finished reads into SLOW5 (ASCII) or BLOW5 (binary) files, following version 0.2.0 of the
+//! [SLOW5 specification](https://hasindu2008.github.io/slow5specs/).
+//!
+//! Both flavours carry the same header attributes, calibration and per read fields as the FAST5 files, and neither needs HDF5
+//! or the VBZ plugin. BLOW5 records can be compressed with zlib or zstd, the signal itself is never compressed separately.
+//!
+//! `python/check_output.py` checks files written here open with `pyslow5`, which is built on slow5lib, and read back as they
+//! were written.
+use std::fs::{create_dir_all, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use crate::output::{
+    FinishedRead, ReadWriter, RunMetadata, CALIBRATION_OFFSET, CALIBRATION_RANGE, DIGITISATION,
+    MEDIAN_BEFORE,
+};
+
+/// The version of the SLOW5 specification that is written
+const SLOW5_VERSION: [u8; 3] = [0, 2, 0];
+/// Magic bytes at the start of a BLOW5 file
+const BLOW5_MAGIC: [u8; 6] = *b"BLOW5\x01";
+/// Magic bytes at the end of a BLOW5 file
+const BLOW5_EOF: [u8; 5] = *b"5WOLB";
+/// The fixed size part of the BLOW5 header is padded out to this many bytes
+const BLOW5_HEADER_SIZE_OFFSET: usize = 64;
+/// Labels of the end reason enum, in the order of the FAST5 end reason enum
+const END_REASON_LABELS: [&str; 8] = [
+    "unknown",
+    "partial",
+    "mux_change",
+    "unblock_mux_change",
+    "data_service_unblock_mux_change",
+    "signal_positive",
+    "signal_negative",
+    "api_request",
+];
+
+/// How each record in a BLOW5 file is compressed.
+#[derive(Clone, Copy, Debug)]
+pub enum RecordCompression {
+    /// Records are written as is
+    None,
+    /// Records are compressed with zlib
+    Zlib,
+    /// Records are compressed with zstd
+    Zstd,
+}
+
+impl RecordCompression {
+    /// The value of the record compression byte in the BLOW5 header
+    fn header_value(&self) -> u8 {
+        match self {
+            RecordCompression::None => 0,
+            RecordCompression::Zlib => 1,
+            RecordCompression::Zstd => 2,
+        }
+    }
+
+    fn compress(&self, record: Vec<u8>) -> io::Result<Vec<u8>> {
+        match self {
+            RecordCompression::None => Ok(record),
+            RecordCompression::Zlib => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(&record)?;
+                encoder.finish()
+            }
+            RecordCompression::Zstd => zstd::encode_all(record.as_slice(), 0),
+        }
+    }
+}
+
+/// Writes each batch of reads into a new SLOW5 or BLOW5 file, in `slow5_pass/` or `blow5_pass/` respectively.
+pub struct Slow5Writer {
+    output_dir: PathBuf,
+    metadata: RunMetadata,
+    file_counter: usize,
+    /// None for ASCII SLOW5, or the record compression to use for BLOW5
+    binary: Option<RecordCompression>,
+}
+
+impl Slow5Writer {
+    /// Create a new writer, creating the output directory inside of the output path if it doesn't already exist.
+    /// Passing a record compression writes BLOW5, otherwise SLOW5 is written.
+    pub fn new(
+        output_path: &Path,
+        metadata: RunMetadata,
+        binary: Option<RecordCompression>,
+    ) -> io::Result<Slow5Writer> {
+        let output_dir = output_path.join(format!("{}_pass", file_extension(binary)));
+        create_dir_all(&output_dir)?;
+        Ok(Slow5Writer {
+            output_dir,
+            metadata,
+            file_counter: 0,
+            binary,
+        })
+    }
+
+    /// The header attributes, column types and column names - shared by both SLOW5 and BLOW5.
+    fn header(&self) -> String {
+        let mut attributes: Vec<(&String, &String)> = self
+            .metadata
+            .tracking_id
+            .iter()
+            .chain(self.metadata.context_tags.iter())
+            .collect();
+        attributes.sort();
+        attributes.dedup_by(|a, b| a.0 == b.0);
+        let mut header = String::new();
+        for (key, value) in attributes {
+            let value = if value.is_empty() { "." } else { value };
+            header.push_str(&format!("@{key}\t{value}\n"));
+        }
+        header.push_str(&format!(
+            "#char*\tuint32_t\tdouble\tdouble\tdouble\tdouble\tuint64_t\tint16_t*\tchar*\tdouble\tint32_t\tuint8_t\tuint64_t\tenum{{{}}}\n",
+            END_REASON_LABELS.join(",")
+        ));
+        header.push_str("#read_id\tread_group\tdigitisation\toffset\trange\tsampling_rate\tlen_raw_signal\traw_signal\tchannel_number\tmedian_before\tread_number\tstart_mux\tstart_time\tend_reason\n");
+        header
+    }
+}
+
+/// The file extension, which is also used to name the output directory.
+fn file_extension(binary: Option<RecordCompression>) -> &'static str {
+    match binary {
+        Some(_) => "blow5",
+        None => "slow5",
+    }
+}
+
+/// A read as a line of an ASCII SLOW5 file.
+fn ascii_record(read: &FinishedRead, sample_rate: f64) -> String {
+    let signal: Vec<String> = read.signal.iter().map(|x| x.to_string()).collect();
+    format!(
+        "{}\t0\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+        read.read_id,
+        DIGITISATION,
+        CALIBRATION_OFFSET,
+        CALIBRATION_RANGE,
+        sample_rate,
+        read.signal.len(),
+        signal.join(","),
+        read.channel,
+        MEDIAN_BEFORE,
+        read.read_number,
+        read.start_mux,
+        read.start_time,
+        read.end_reason.fast5_value()
+    )
+}
+
+/// A read as an uncompressed BLOW5 record. Fixed width fields are little endian, variable length arrays are prefixed with
+/// their length - a u16 for the read id and a u64 for everything else.
+fn binary_record(read: &FinishedRead, sample_rate: f64) -> Vec<u8> {
+    let channel_number = read.channel.to_string();
+    let mut record = Vec::with_capacity(read.signal.len() * 2 + 128);
+    record.extend((read.read_id.len() as u16).to_le_bytes());
+    record.extend(read.read_id.as_bytes());
+    record.extend(0_u32.to_le_bytes());
+    record.extend(DIGITISATION.to_le_bytes());
+    record.extend(CALIBRATION_OFFSET.to_le_bytes());
+    record.extend(CALIBRATION_RANGE.to_le_bytes());
+    record.extend(sample_rate.to_le_bytes());
+    record.extend((read.signal.len() as u64).to_le_bytes());
+    for sample in &read.signal {
+        record.extend(sample.to_le_bytes());
+    }
+    record.extend((channel_number.len() as u64).to_le_bytes());
+    record.extend(channel_number.as_bytes());
+    record.extend(MEDIAN_BEFORE.to_le_bytes());
+    record.extend((read.read_number as i32).to_le_bytes());
+    record.push(read.start_mux);
+    record.extend(read.start_time.to_le_bytes());
+    record.push(read.end_reason.fast5_value());
+    record
+}
+
+impl ReadWriter for Slow5Writer {
+    fn write_reads(&mut self, reads: &[FinishedRead]) -> io::Result<PathBuf> {
+        let file_name = self.output_dir.join(format!(
+            "{}_pass_{}_{}.{}",
+            self.metadata.flowcell_name,
+            &self.metadata.run_id[0..6],
+            self.file_counter,
+            file_extension(self.binary)
+        ));
+        let mut file = BufWriter::new(File::create(&file_name)?);
+        let header = self.header();
+        match self.binary {
+            None => {
+                let [major, minor, patch] = SLOW5_VERSION;
+                write!(
+                    file,
+                    "#slow5_version\t{major}.{minor}.{patch}\n#num_read_groups\t1\n{header}"
+                )?;
+                for read in reads {
+                    file.write_all(ascii_record(read, self.metadata.sample_rate).as_bytes())?;
+                }
+            }
+            Some(compression) => {
+                let mut fixed_header = Vec::with_capacity(BLOW5_HEADER_SIZE_OFFSET);
+                fixed_header.extend(BLOW5_MAGIC);
+                fixed_header.extend(SLOW5_VERSION);
+                fixed_header.push(compression.header_value());
+                fixed_header.extend(1_u32.to_le_bytes());
+                // signal compression - none
+                fixed_header.push(0);
+                fixed_header.resize(BLOW5_HEADER_SIZE_OFFSET, 0);
+                file.write_all(&fixed_header)?;
+                file.write_all(&(header.len() as u32).to_le_bytes())?;
+                file.write_all(header.as_bytes())?;
+                for read in reads {
+                    let record =
+                        compression.compress(binary_record(read, self.metadata.sample_rate))?;
+                    file.write_all(&(record.len() as u64).to_le_bytes())?;
+                    file.write_all(&record)?;
+                }
+                file.write_all(&BLOW5_EOF)?;
+            }
+        }
+        file.flush()?;
+        self.file_counter += 1;
+        Ok(file_name)
+    }
+}
@@ -0,0 +1,190 @@
+//! Loads pore models - tables of the current level for every kmer - that signal is simulated from.
+//!
+//! Accepts the tables from ONT's [kmer_models](https://github.com/nanoporetech/kmer_models) repository, both the R9.4.1 6-mer
+//! models with a `kmer level_mean level_stdv ...` header and the headerless R10.4.1 9-mer level tables, as well as the
+//! two column `kmer<TAB>level` files previously used by Icarust. The kmer size is worked out from the file.
+//!
+//! Levels are held in picoamps. The R9.4.1 models are already in picoamps, while the R10.4.1 level tables are normalised to
+//! a mean of 0 and a standard deviation of 1, so their levels are moved onto the current of R10 signal when they are loaded.
+//!
+//! Modified kmer tables are read the same way, with modified bases written as the letters in
+//! [`modifications::KMER_LETTERS`]. They only need the kmers that contain a modified base.
+//!
+use std::error::Error;
+use std::fs::read_to_string;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+use fnv::{FnvHashMap, FnvHasher};
+
+use crate::modifications;
+
+/// If the pore model has no standard deviations, each level is given this fraction of the spread of all the levels in the model
+const DEFAULT_STDV_FRACTION: f64 = 0.1;
+/// Tables with a mean level below this are normalised rather than in picoamps
+const NORMALISED_MAX_MEAN: f64 = 10.0;
+/// Mean current in picoamps that normalised levels are moved to, that of the R10 squiggle shipped in static/
+const NORMALISED_MEAN_PICOAMPS: f64 = 98.0;
+/// Standard deviation of the current in picoamps that normalised levels are scaled to, that of the R10 squiggle shipped in
+/// static/
+const NORMALISED_STDV_PICOAMPS: f64 = 24.0;
+/// Names a header might give the column of kmers
+const KMER_COLUMNS: [&str; 1] = ["kmer"];
+/// Names a header might give the column of mean levels, in order of preference
+const MEAN_COLUMNS: [&str; 3] = ["level_mean", "level", "mean"];
+/// Names a header might give the column of level standard deviations, in order of preference
+const STDV_COLUMNS: [&str; 2] = ["level_stdv", "stdv"];
+
+/// The current level of a kmer in the pore model
+#[derive(Clone, Copy, Debug)]
+pub struct KmerLevel {
+    /// Mean current level in picoamps
+    pub mean: f64,
+    /// Standard deviation of the current level in picoamps
+    pub stdv: f64,
+}
+
+/// A pore model, the current level for each kmer of length k
+#[derive(Debug)]
+pub struct PoreModel {
+    /// The length of the kmers in the model
+    pub k: usize,
+    levels: FnvHashMap<String, KmerLevel>,
+    /// Hash of the table the model was parsed from
+    digest: u64,
+}
+
+impl PoreModel {
+    /// Read a pore model from a file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<PoreModel, Box<dyn Error>> {
+        let contents = read_to_string(&path).map_err(|err| {
+            format!(
+                "Failed to read pore model {} - {err}",
+                path.as_ref().display()
+            )
+        })?;
+        PoreModel::parse(&contents)
+    }
+
+    /// Parse a whitespace separated pore model table. Blank lines and lines starting with # are ignored.
+    /// If the first line has a header the kmer, mean and standard deviation columns are found by name, otherwise they are
+    /// taken to be the first, second and (if there is one) third columns.
+    pub fn parse(contents: &str) -> Result<PoreModel, Box<dyn Error>> {
+        let mut lines = contents
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .peekable();
+        let first_line: Vec<&str> = lines
+            .peek()
+            .ok_or("Pore model is empty")?
+            .split_whitespace()
+            .collect();
+        let (kmer_column, mean_column, stdv_column) = if is_kmer(first_line[0]) {
+            (0, 1, (first_line.len() > 2).then_some(2))
+        } else {
+            lines.next();
+            let find = |names: &[&str]| {
+                names.iter().find_map(|name| {
+                    first_line
+                        .iter()
+                        .position(|column| column.eq_ignore_ascii_case(name))
+                })
+            };
+            (
+                find(&KMER_COLUMNS).ok_or("Pore model header has no kmer column")?,
+                find(&MEAN_COLUMNS).ok_or("Pore model header has no level_mean column")?,
+                find(&STDV_COLUMNS),
+            )
+        };
+
+        let mut k = 0;
+        let mut records: Vec<(String, f64, Option<f64>)> = vec![];
+        for line in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let field = |column: usize| {
+                fields
+                    .get(column)
+                    .copied()
+                    .ok_or_else(|| format!("Pore model line is missing columns - {line}"))
+            };
+            let kmer = field(kmer_column)?.to_uppercase().replace('U', "T");
+            if !is_kmer(&kmer) {
+                return Err(format!("Invalid kmer {kmer} in pore model").into());
+            }
+            if k == 0 {
+                k = kmer.len();
+            } else if kmer.len() != k {
+                return Err(
+                    format!("Pore model has kmers of length {k} and {}", kmer.len()).into(),
+                );
+            }
+            let mean: f64 = field(mean_column)?.parse()?;
+            let stdv: Option<f64> = match stdv_column {
+                Some(column) => Some(field(column)?.parse()?),
+                None => None,
+            };
+            records.push((kmer, mean, stdv));
+        }
+        // modified kmer tables only have the kmers with a modified base
+        let modified = records
+            .iter()
+            .any(|(kmer, _, _)| kmer.contains(modifications::KMER_LETTERS));
+        if !modified && records.len() != 4_usize.pow(k as u32) {
+            warn!(
+                "Pore model has {} kmers, expected {} for k = {k}",
+                records.len(),
+                4_usize.pow(k as u32)
+            );
+        }
+
+        let n = records.len() as f64;
+        let mean = records.iter().map(|x| x.1).sum::<f64>() / n;
+        let spread = (records.iter().map(|x| (x.1 - mean).powi(2)).sum::<f64>() / n).sqrt();
+        // normalised tables are moved onto the current of real signal, so every model is simulated in picoamps
+        let (scale, shift) = match mean < NORMALISED_MAX_MEAN {
+            true => {
+                info!("Pore model levels are normalised, scaling them to picoamps");
+                (NORMALISED_STDV_PICOAMPS, NORMALISED_MEAN_PICOAMPS)
+            }
+            false => (1.0, 0.0),
+        };
+        let levels = records
+            .into_iter()
+            .map(|(kmer, mean, stdv)| {
+                let level = KmerLevel {
+                    mean: mean * scale + shift,
+                    stdv: stdv.unwrap_or(spread * DEFAULT_STDV_FRACTION) * scale,
+                };
+                (kmer, level)
+            })
+            .collect();
+        let mut hasher = FnvHasher::default();
+        contents.hash(&mut hasher);
+        info!("Loaded pore model with k = {k}");
+        Ok(PoreModel {
+            k,
+            levels,
+            digest: hasher.finish(),
+        })
+    }
+
+    /// Get the level of a kmer, which must be uppercase.
+    pub fn level(&self, kmer: &str) -> Option<&KmerLevel> {
+        self.levels.get(kmer)
+    }
+
+    /// A hash of the pore model table, to tell whether signal was simulated with this model.
+    pub fn digest(&self) -> u64 {
+        self.digest
+    }
+}
+
+/// Whether a string is a nucleotide kmer, possibly with modified bases, rather than a column name.
+fn is_kmer(value: &str) -> bool {
+    !value.is_empty()
+        && value.chars().all(|c| {
+            let c = c.to_ascii_uppercase();
+            matches!(c, 'A' | 'C' | 'G' | 'T' | 'U') || modifications::KMER_LETTERS.contains(&c)
+        })
+}
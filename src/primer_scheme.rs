@@ -0,0 +1,75 @@
+//! Reads primer scheme BED files, such as the ARTIC schemes, into the amplicons they produce.
+//!
+//! Each line of the BED file is a primer - chromosome, start, end and a name like `MPV_4_LEFT`, where the number after the
+//! first underscore is the amplicon the primer belongs to. Alternate primers (`MPV_4_RIGHT_alt1`) belong to the same
+//! amplicon, which spans from the start of its leftmost primer to the end of its rightmost primer.
+//!
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::read_to_string;
+use std::path::Path;
+
+/// An amplicon from a primer scheme, spanning the reference from start to end
+#[derive(Debug, Clone)]
+pub struct Amplicon {
+    /// The reference sequence the primers are on
+    pub chrom: String,
+    /// Start of the leftmost primer, 0 based
+    pub start: usize,
+    /// End of the rightmost primer, exclusive
+    pub end: usize,
+    /// The name of the first primer of the amplicon in the BED file
+    pub name: String,
+}
+
+/// Collapse the primers in a primer scheme BED file into the start and end of each amplicon. Primers are grouped by
+/// chromosome and the number in their name, and the amplicon is named after its first primer. Amplicons are returned in
+/// the order they first appear in the file.
+pub fn collapse_amplicon_start_ends(
+    scheme_bed_file: &Path,
+) -> Result<Vec<Amplicon>, Box<dyn Error>> {
+    let mut primers: Vec<(String, usize, usize, usize, String)> = vec![];
+    for line in read_to_string(scheme_bed_file)?.lines() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if line.trim().is_empty() || line.starts_with('#') || fields.len() < 4 {
+            continue;
+        }
+        let primer_number: usize = fields[3]
+            .split('_')
+            .nth(1)
+            .and_then(|number| number.parse().ok())
+            .ok_or_else(|| format!("Can't find the amplicon number of primer {}", fields[3]))?;
+        primers.push((
+            fields[0].to_string(),
+            primer_number,
+            fields[1].parse()?,
+            fields[2].parse()?,
+            fields[3].to_string(),
+        ));
+    }
+    let mut spans: HashMap<(&str, usize), (usize, usize)> = HashMap::new();
+    for (chrom, number, start, end, _) in &primers {
+        let span = spans
+            .entry((chrom.as_str(), *number))
+            .or_insert((*start, *end));
+        span.0 = span.0.min(*start);
+        span.1 = span.1.max(*end);
+    }
+    let mut seen: HashSet<(&str, usize, usize)> = HashSet::new();
+    let mut amplicons = vec![];
+    for (chrom, number, _, _, name) in &primers {
+        let (start, end) = spans[&(chrom.as_str(), *number)];
+        if seen.insert((chrom.as_str(), start, end)) {
+            amplicons.push(Amplicon {
+                chrom: chrom.clone(),
+                start,
+                end,
+                name: name.clone(),
+            });
+        }
+    }
+    if amplicons.is_empty() {
+        return Err(format!("No primers found in {}", scheme_bed_file.display()).into());
+    }
+    Ok(amplicons)
+}
@@ -1,25 +1,24 @@
-//! Defines code used to create the R10 signal from pore models.
-use fnv::{FnvHashMap, FnvHashSet};
+//! Defines code used to create signal from sequence, using pore models. Originally R10 only, but works for any pore model.
+use fnv::FnvHashSet;
 use indicatif::{ProgressBar, ProgressStyle};
 use lazy_static::lazy_static;
 use memmap2::Mmap;
 use ndarray::ArrayView1;
 use ndarray_npy::ViewNpyExt;
 use needletail::parse_fastx_file;
-use needletail::parser::SequenceRecord;
 use needletail::{FastxReader, Sequence};
-use nom::character::complete::{alpha1, multispace0, tab};
-use nom::multi::many1;
-use nom::number::complete::double;
-use nom::sequence::{separated_pair, terminated};
-use nom::IResult;
 use rand::seq::SliceRandom;
+use rand::Rng;
+use rand_distr::{Distribution, Exp, Gamma, Normal};
 use std::borrow::Cow;
-use std::collections::HashMap;
 use std::error::Error;
 use std::fs::File;
 use std::path::Path;
 
+use crate::modifications::Modification;
+use crate::output::{picoamps_to_raw, raw_to_picoamps};
+use crate::pore_model::PoreModel;
+
 lazy_static! {
     static ref HASHSET: FnvHashSet<char> = {
         let mut set = FnvHashSet::default();
@@ -72,20 +71,84 @@ pub fn normalize(seq: &[u8]) -> Option<Vec<u8>> {
     }
 }
 
-/// hold a kmer
-pub struct Kmer {
-    /// The 9mer sequence
-    pub sequence: String,
-    /// The value for the signal of that 9mer
-    pub value: f64,
+/// Digitisation the R10 adapter and barcode squiggle in static/ were simulated with
+const STATIC_R10_DIGITISATION: f64 = 2048.0;
+/// Range in picoamps the R10 adapter and barcode squiggle in static/ were simulated with
+const STATIC_R10_RANGE: f64 = 200.0;
+/// How the number of samples spent on each base is chosen, from the simulation profile
+#[derive(Clone, Copy, Debug)]
+pub enum DwellDistribution {
+    /// Every base gets exactly the mean number of samples
+    Fixed,
+    /// Samples per base are gamma distributed, with the given standard deviation
+    Gamma(f64),
+    /// Samples per base are exponentially distributed
+    Exponential,
+}
+
+/// Draws the number of samples each base spends in the pore.
+pub enum Dwell {
+    /// The same dwell for every base
+    Fixed(f64),
+    /// Gamma distributed dwell
+    Gamma(Gamma<f64>),
+    /// Exponentially distributed dwell
+    Exponential(Exp<f64>),
+}
+
+impl Dwell {
+    /// Create the dwell distribution around the mean number of samples per base.
+    pub fn new(distribution: DwellDistribution, mean: f64) -> Dwell {
+        match distribution {
+            DwellDistribution::Fixed => Dwell::Fixed(mean),
+            DwellDistribution::Gamma(stdv) => {
+                // shape * scale is the mean, shape * scale^2 is the variance
+                let scale = stdv.powi(2) / mean;
+                Dwell::Gamma(Gamma::new(mean / scale, scale).expect("Invalid dwell distribution"))
+            }
+            DwellDistribution::Exponential => {
+                Dwell::Exponential(Exp::new(1.0 / mean).expect("Invalid dwell distribution"))
+            }
+        }
+    }
+
+    /// Draw the number of samples for the next base.
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> f64 {
+        match self {
+            Dwell::Fixed(dwell) => *dwell,
+            Dwell::Gamma(gamma) => gamma.sample(rng),
+            Dwell::Exponential(exp) => exp.sample(rng),
+        }
+    }
 }
 
 /// Profile for sequencing
 pub struct R10Settings {
-    /// Digitisation to i16 I dunno
-    digitisation: f64,
-    /// range
-    range: f64,
+    /// How many samples each base spends in the pore, the mean is the sample rate divided by the bases per second
+    dwell: Dwell,
+    /// Multiplier on the standard deviation of each kmer level, 0 gives a noiseless signal
+    level_noise: f64,
+}
+
+impl R10Settings {
+    /// Whether simulating the same sequence twice can give different signal, because of level noise or variable dwell.
+    pub fn is_random(&self) -> bool {
+        self.level_noise > 0.0 || !matches!(self.dwell, Dwell::Fixed(_))
+    }
+}
+
+/// A pore model and the profile to simulate signal from sequence with
+pub struct Simulator {
+    /// Levels of each kmer
+    pub model: PoreModel,
+    /// Digitisation, dwell and noise
+    pub profile: R10Settings,
+    /// Hash of the pore model and every setting that changes the simulated signal, for the signal cache
+    pub fingerprint: u64,
+    /// Whether sequence is reversed before simulating, as direct RNA is read 3' to 5'
+    pub reverse: bool,
+    /// Levels of kmers containing modified bases, if any samples are modified
+    pub modified_model: Option<PoreModel>,
 }
 
 /// Simulation type - Promethion or MInion. We always use Promethion
@@ -98,38 +161,35 @@ pub enum SimType {
 const RANDOM_CHARS: [char; 4] = ['A', 'C', 'G', 'T'];
 
 /// return the simulation profile for a given simulation type
-pub fn get_sim_profile(sim_type: SimType) -> R10Settings {
+pub fn get_sim_profile(
+    sim_type: SimType,
+    samples_per_base: f64,
+    dwell_distribution: DwellDistribution,
+    level_noise: f64,
+) -> R10Settings {
     match sim_type {
         SimType::R10 => R10Settings {
-            digitisation: 2048.0,
-            range: 200.0,
+            dwell: Dwell::new(dwell_distribution, samples_per_base),
+            level_noise,
         },
     }
 }
 
-/// Use nom to parse an individual line in the kmers file
-pub fn parse_kmer_record(input: &str) -> IResult<&str, Kmer> {
-    let (remaining, (kmer, value)) =
-        separated_pair(alpha1, tab, terminated(double, multispace0))(input)?;
-    let kmer = kmer.to_string();
-    Ok((
-        remaining,
-        Kmer {
-            sequence: kmer,
-            value,
-        },
-    ))
+/// Scale and shift the signal of a whole read, as the levels of real reads vary from read to read.
+/// The current of each sample is scaled, then shifted by `shift` picoamps.
+pub fn scale_and_shift(signal: &mut [i16], scale: f64, shift: f64) {
+    for sample in signal.iter_mut() {
+        *sample = picoamps_to_raw(raw_to_picoamps(*sample) * scale + shift);
+    }
 }
 
-/// Parse kmers and corresponding values from the file
-pub fn parse_kmers(input: &str) -> IResult<&str, FnvHashMap<String, f64>> {
-    let (remainder, kmer_vec) = many1(parse_kmer_record)(input)?;
-    let mut kmers: HashMap<String, f64, std::hash::BuildHasherDefault<fnv::FnvHasher>> =
-        FnvHashMap::default();
-    for x in kmer_vec {
-        kmers.insert(x.sequence, x.value);
-    }
-    Ok((remainder, kmers))
+/// Convert R10 squiggle from static/, which was simulated with its own digitisation and range, to the calibration of the
+/// output files.
+pub fn calibrate_static_r10(signal: Vec<i16>) -> Vec<i16> {
+    signal
+        .into_iter()
+        .map(|x| picoamps_to_raw(x as f64 * STATIC_R10_RANGE / STATIC_R10_DIGITISATION))
+        .collect()
 }
 
 /// Generate signal for a stall sequence and adpator DNA
@@ -137,7 +197,7 @@ pub fn generate_prefix() -> Result<Vec<i16>, Box<dyn Error>> {
     let file = File::open("static/prefix.squiggle.npy").unwrap();
     let mmap = unsafe { Mmap::map(&file).unwrap() };
     let view: Vec<i16> = ArrayView1::<i16>::view_npy(&mmap).unwrap().to_vec();
-    Ok(view)
+    Ok(calibrate_static_r10(view))
 }
 
 /// Replace all occurences of a character in a string with a randomly chosen A,C,G, or T.
@@ -172,26 +232,18 @@ pub fn num_sequences<P: AsRef<Path> + std::fmt::Debug>(path: P) -> usize {
     num_seq
 }
 
-/// Get the lengths of all contigs in a given FASTA/FASTQ file
-pub fn sequence_lengths<P: AsRef<Path> + std::fmt::Debug>(path: P) -> Vec<usize> {
-    let mut read_lengths = vec![];
-    let mut reader: Box<dyn FastxReader> =
-        parse_fastx_file(&path).unwrap_or_else(|_| panic!("Can't find FASTA file at {path:#?}"));
-    while let Some(record) = reader.next() {
-        read_lengths.push(record.unwrap().num_bases())
-    }
-    read_lengths
-}
-
-/// Convert a given FASTA sequence to signal, digitising it and return a Vector of I16
-pub fn convert_to_signal<'a>(
-    kmers: &FnvHashMap<String, f64>,
-    record: &SequenceRecord,
+/// Convert a given FASTA sequence to signal, digitising it and return a Vector of I16.
+pub fn convert_to_signal<'a, R: Rng>(
+    model: &PoreModel,
+    sequence: &'a [u8],
     profile: &R10Settings,
+    rng: &mut R,
 ) -> Result<Vec<i16>, Box<dyn Error>> {
-    let mut signal_vec: Vec<i16> = Vec::with_capacity(record.num_bases() * 10);
-    let r: Cow<'a, [u8]> = normalize(record.sequence()).unwrap().into();
-    let num_kmers = r.len() - 8;
+    let r: Cow<'a, [u8]> = match normalize(sequence) {
+        Some(normalized) => normalized.into(),
+        None => sequence.into(),
+    };
+    let num_kmers = r.len().saturating_sub(model.k - 1);
     let sty = ProgressStyle::with_template(
         "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}",
     )
@@ -199,29 +251,147 @@ pub fn convert_to_signal<'a>(
     .progress_chars("##-");
     let pb = ProgressBar::new(num_kmers.try_into().unwrap());
     pb.set_style(sty);
-    for kmer in r.kmers(9) {
+    let signal_vec = sequence_to_signal(model, &r, profile, rng, &pb);
+    pb.finish_with_message("done");
+
+    Ok(signal_vec)
+}
+
+/// Simulate the signal for a sequence of bases, incrementing the progress bar for each kmer.
+/// Each base spends a number of samples drawn from the dwell distribution in the pore, and each sample is drawn from the
+/// level of the kmer in the pore.
+pub fn sequence_to_signal<R: Rng>(
+    model: &PoreModel,
+    sequence: &[u8],
+    profile: &R10Settings,
+    rng: &mut R,
+    pb: &ProgressBar,
+) -> Vec<i16> {
+    simulate_signal(model, None, sequence, profile, rng, pb)
+}
+
+/// Simulate the signal for a sequence of bases with modified bases, given by their position in the sequence in order.
+/// Kmers containing a modified base take their level from the modified kmer table, or from the pore model if the table
+/// doesn't have them.
+pub fn modified_sequence_to_signal<R: Rng>(
+    model: &PoreModel,
+    modified_model: &PoreModel,
+    modified: &[(usize, Modification)],
+    sequence: &[u8],
+    profile: &R10Settings,
+    rng: &mut R,
+    pb: &ProgressBar,
+) -> Vec<i16> {
+    simulate_signal(
+        model,
+        Some((modified_model, modified)),
+        sequence,
+        profile,
+        rng,
+        pb,
+    )
+}
+
+/// Simulate the signal for a sequence, with the modified kmer table and modified bases if there are any.
+fn simulate_signal<R: Rng>(
+    model: &PoreModel,
+    modifications: Option<(&PoreModel, &[(usize, Modification)])>,
+    sequence: &[u8],
+    profile: &R10Settings,
+    rng: &mut R,
+    pb: &ProgressBar,
+) -> Vec<i16> {
+    let mut signal_vec: Vec<i16> = Vec::with_capacity(sequence.len() * 10);
+    let noise = Normal::new(0.0, 1.0).unwrap();
+    // samples per base isn't always a whole number, so carry over the fraction of a sample to the next base
+    let mut samples_owed: f64 = 0.0;
+    // the first modified base that hasn't left the pore yet
+    let mut next_modified = 0;
+    for (position, kmer) in sequence.kmers(model.k as u8).enumerate() {
         let mut kmer = String::from_utf8(kmer.to_vec()).unwrap();
-        kmer = replace_char_with_base(&kmer, None);
+        kmer = replace_char_with_base(&kmer, None).to_uppercase();
         debug!("{kmer}");
-        let value = kmers.get(&kmer.to_uppercase()).unwrap_or_else(|| {
-            panic!(
-                "failed to retrieve value for kmer {kmer}, on contig{}",
-                String::from_utf8(record.id().to_vec()).unwrap()
-            )
-        });
-        debug!("{value}");
-
-        let x = (value * profile.digitisation) / profile.range;
-        // 10 sample for each base (sample_rate (4000) / base per second (400))
-        // could also be worked out from profile.dwell_mean
-        for _ in 0..10 {
-            signal_vec.push(x as i16);
+        let mut level = model
+            .level(&kmer)
+            .unwrap_or_else(|| panic!("failed to retrieve value for kmer {kmer} from pore model"));
+        if let Some((modified_model, modified)) = modifications {
+            while next_modified < modified.len() && modified[next_modified].0 < position {
+                next_modified += 1;
+            }
+            let in_pore = modified[next_modified..]
+                .iter()
+                .take_while(|(modified_position, _)| *modified_position < position + model.k);
+            let mut modified_kmer = kmer.clone().into_bytes();
+            for (modified_position, modification) in in_pore {
+                modified_kmer[modified_position - position] = modification.kmer_letter();
+            }
+            let modified_kmer = String::from_utf8(modified_kmer).unwrap();
+            if modified_kmer != kmer {
+                level = modified_model.level(&modified_kmer).unwrap_or(level);
+            }
+        }
+        debug!("{level:?}");
+
+        // on average sample_rate / bases per second samples for each base, i.e 10 at 4000 Hz and 400 bases per second
+        samples_owed += profile.dwell.sample(rng);
+        while samples_owed >= 1.0 {
+            let value: f64 = level.mean + noise.sample(rng) * level.stdv * profile.level_noise;
+            signal_vec.push(picoamps_to_raw(value));
+            samples_owed -= 1.0;
         }
         pb.inc(1);
     }
-    pb.finish_with_message("done");
+    signal_vec
+}
 
-    Ok(signal_vec)
+/// The bases each two bit code in a [`PackedSequence`] stands for
+const PACKED_BASES: [u8; 4] = *b"ACGT";
+
+/// A sequence packed into two bits per base, a quarter of a byte rather than the ~20 bytes of simulated signal, so that
+/// whole genomes can be held in memory and the signal simulated a read at a time.
+#[derive(Debug)]
+pub struct PackedSequence {
+    /// Four bases to a byte, first base in the lowest bits
+    bases: Vec<u8>,
+    /// Number of bases
+    len: usize,
+}
+
+impl PackedSequence {
+    /// Pack a sequence. Whitespace is dropped, U is read as T and anything else that isn't a base is replaced with a random
+    /// base, as Ns are when converting whole sequences.
+    pub fn new<R: Rng>(sequence: &[u8], rng: &mut R) -> PackedSequence {
+        let mut bases: Vec<u8> = Vec::with_capacity(sequence.len() / 4 + 1);
+        let mut len = 0;
+        for base in sequence {
+            let code: u8 = match base.to_ascii_uppercase() {
+                b'A' => 0,
+                b'C' => 1,
+                b'G' => 2,
+                b'T' | b'U' => 3,
+                b' ' | b'\t' | b'\r' | b'\n' => continue,
+                _ => rng.gen_range(0..4),
+            };
+            if len % 4 == 0 {
+                bases.push(0);
+            }
+            bases[len / 4] |= code << (2 * (len % 4));
+            len += 1;
+        }
+        PackedSequence { bases, len }
+    }
+
+    /// The number of bases in the sequence.
+    pub fn num_bases(&self) -> usize {
+        self.len
+    }
+
+    /// Unpack the bases from start up to (but not including) end, which is clamped to the end of the sequence.
+    pub fn unpack(&self, start: usize, end: usize) -> Vec<u8> {
+        (start..end.min(self.len))
+            .map(|i| PACKED_BASES[((self.bases[i / 4] >> (2 * (i % 4))) & 0b11) as usize])
+            .collect()
+    }
 }
 
 // read_tag, u32
@@ -5,11 +5,8 @@
 //!
 //!
 
-use ndarray::prelude::*;
 use rand::prelude::*;
-use std::ops::Neg;
-
-use rand::seq::SliceRandom;
+use rand_distr::Exp;
 use std::f64;
 
 //  Lifted from statrs https://docs.rs/statrs/latest/src/statrs/function/gamma.rs.html#1-808
@@ -40,7 +37,7 @@ const GAMMA_DK: &[f64] = &[
 ///  let x = gamma_fac(0.01)
 ///  assert!(x == 0.9943258511915062);
 /// ```
-fn gamma_fac(x: f64) -> f64 {
+pub fn gamma_fac(x: f64) -> f64 {
     if x < 0.0 {
         0.0
     } else if x == 0.0 {
@@ -69,34 +66,68 @@ fn gamma_fac(x: f64) -> f64 {
     }
 }
 
-/// Models the reacquisition time for a pore.
-pub struct ReacquisitionPoisson {
-    t: Vec<f64>,
-}
-
 pub trait SampleDist {
     fn sample<R>(&self, rng: &mut R) -> f64
     where
         R: Rng;
 }
 
-impl ReacquisitionPoisson {
-    /// Return a new Reacquisition poisson.
-    /// Upper is the maximum possible upper value
-    /// Lower is the lowest possible value
-    /// step is how tight the distribution curves
-    /// k is the skew ( I think)
-    pub fn new(upper: f64, lower: f64, step: f64, k: f64) -> ReacquisitionPoisson {
-        let mut t = Array::range(lower, upper, step);
-        t.mapv_inplace(|x| ((k.powf(x)) * k.neg().exp()) / gamma_fac(x));
-        let t = t.to_vec();
-        ReacquisitionPoisson { t }
-    }
+/// Models how long an open pore waits to capture its next molecule.
+///
+/// Capture is a Poisson process, so the time to capture is exponentially distributed. The capture rate rises with the
+/// concentration of the library and, as short molecules reach the pore faster, falls with the length of the molecule. It
+/// decays exponentially as the library is used up over the run.
+#[derive(Debug, Clone)]
+pub struct CaptureModel {
+    /// Mean seconds to capture a molecule of the reference length, at a concentration of 1, at the start of the run
+    mean_time_to_capture: f64,
+    /// Concentration of the library, relative to the one mean_time_to_capture is for
+    concentration: f64,
+    /// Length in bases that mean_time_to_capture is for
+    reference_length: f64,
+    /// How strongly the capture rate depends on length, the rate going with (reference_length / length) ^ length_exponent
+    length_exponent: f64,
+    /// Seconds for the capture rate to halve as the library is used up
+    half_life: Option<f64>,
 }
 
-impl SampleDist for ReacquisitionPoisson {
-    fn sample<R: rand::RngCore>(&self, rng: &mut R) -> f64 {
-        *self.t.choose(rng).unwrap()
+impl CaptureModel {
+    pub fn new(
+        mean_time_to_capture: f64,
+        concentration: f64,
+        reference_length: f64,
+        length_exponent: f64,
+        half_life: Option<f64>,
+    ) -> CaptureModel {
+        CaptureModel {
+            mean_time_to_capture,
+            concentration,
+            reference_length,
+            length_exponent,
+            half_life,
+        }
+    }
+
+    /// The rate per second an open pore captures a molecule of `length` bases, `elapsed` seconds into the run.
+    pub fn rate(&self, elapsed: f64, length: f64) -> f64 {
+        let depletion = match self.half_life {
+            Some(half_life) => 0.5_f64.powf(elapsed / half_life),
+            None => 1.0,
+        };
+        let length_factor = (self.reference_length / length.max(1.0)).powf(self.length_exponent);
+        self.concentration * depletion * length_factor / self.mean_time_to_capture
+    }
+
+    /// The mean seconds to capture a molecule of the reference length, `elapsed` seconds into the run.
+    pub fn mean_time_to_capture(&self, elapsed: f64) -> f64 {
+        1.0 / self.rate(elapsed, self.reference_length)
+    }
+
+    /// Draw the seconds an open pore waits to capture a molecule of `length` bases, `elapsed` seconds into the run.
+    pub fn time_to_capture<R: Rng>(&self, elapsed: f64, length: f64, rng: &mut R) -> f64 {
+        Exp::new(self.rate(elapsed, length))
+            .map(|exp| exp.sample(rng))
+            .unwrap_or(f64::INFINITY)
     }
 }
 
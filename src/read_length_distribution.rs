@@ -1,29 +1,316 @@
-use crate::reacquisition_distribution::SampleDist;
-// use rand_distr::{Poisson, Distribution};
+//! Read length distributions - the length of each molecule read from a sample.
+//!
+//! Lengths are drawn in bases, clipped to the sample's minimum and maximum, and then converted to samples of signal with the
+//! configured sample rate and translocation speed.
+//!
+//! Lengths can also be learned from real data - the reads of a FASTQ, the `sequence_length_template` column of a
+//! `sequencing_summary.txt` or a plain list of lengths - so simulated reads match the fragment profile of a real library.
+//!
+use crate::impl_services::data::FileExtension;
+use crate::reacquisition_distribution::{gamma_fac, SampleDist};
 use rand::prelude::*;
-use std::cmp::Ordering;
+use std::error::Error;
+use std::fs::{read_to_string, File};
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
 
-use rand::seq::SliceRandom;
-use rand_distr::{Distribution, FisherF};
+use flate2::read::MultiGzDecoder;
+use needletail::parse_fastx_file;
+use rand::distributions::WeightedIndex;
+use rand_distr::{Distribution, FisherF, Gamma, LogNormal, Weibull};
 
 const LOGLOG_SLOPE: f64 = -2.211897875506251;
 const LOGLOG_INTERCEPT: f64 = 1.002555437670879;
 const DF2: f64 = 500.0;
+/// The mean of the F distribution with DF2 denominator degrees of freedom
+const F_MEAN: f64 = DF2 / (DF2 - 2.0);
 
 const K1_SLOPE: f64 = 0.5670830069364579;
 const K1_INTERCEPT: f64 = -0.09239985798819927;
 const K2_SLOPE: f64 = 0.5823114978219056;
 const K2_INTERCEPT: f64 = -0.11748300123471256;
 
+/// Skew of the default read length distribution
+const SKEW: f64 = 1.25;
+/// Column of a sequencing summary holding the length of each read
+const SUMMARY_LENGTH_COLUMN: &str = "sequence_length_template";
+
+/// The shape of a read length distribution, in bases
+#[derive(Debug)]
+pub enum LengthDistribution {
+    /// A right skewed distribution made by scaling an F distribution
+    Skew {
+        f: FisherF<f64>,
+        scale_factor: f64,
+        mean: f64,
+    },
+    /// A log-normal distribution and its mean
+    LogNormal(LogNormal<f64>, f64),
+    /// A gamma distribution and its mean
+    Gamma(Gamma<f64>, f64),
+    /// A Weibull distribution and its mean
+    Weibull(Weibull<f64>, f64),
+    /// Every read is the same length
+    Fixed(f64),
+    /// Bins of lengths, a bin being chosen by its weight and the length drawn uniformly from within it
+    Histogram {
+        bins: Vec<(f64, f64)>,
+        weights: WeightedIndex<f64>,
+        mean: f64,
+    },
+    /// Lengths of real reads
+    Empirical(EmpiricalLengths),
+}
+
+impl LengthDistribution {
+    /// The default skewed distribution, with a standard deviation of half the mean unless one is given.
+    pub fn skew(mean: f64, stdv: Option<f64>) -> Result<LengthDistribution, Box<dyn Error>> {
+        let stdv = stdv.unwrap_or(mean / 2.0);
+        let df1 = 10.0_f64.powf((LOGLOG_SLOPE * SKEW.log10()) + LOGLOG_INTERCEPT);
+        let scaling_slope: f64 = SKEW * K1_SLOPE + K1_INTERCEPT;
+        let scaling_intercept: f64 = SKEW * K2_SLOPE + K2_INTERCEPT;
+        let scale_factor: f64 = (stdv - scaling_intercept) / scaling_slope;
+        Ok(LengthDistribution::Skew {
+            f: FisherF::new(df1, DF2)?,
+            scale_factor,
+            mean,
+        })
+    }
+
+    /// A log-normal distribution with the given mean and standard deviation, by default half the mean.
+    pub fn log_normal(mean: f64, stdv: Option<f64>) -> Result<LengthDistribution, Box<dyn Error>> {
+        let stdv = stdv.unwrap_or(mean / 2.0);
+        let sigma_squared = (1.0 + (stdv / mean).powi(2)).ln();
+        let mu = mean.ln() - sigma_squared / 2.0;
+        Ok(LengthDistribution::LogNormal(
+            LogNormal::new(mu, sigma_squared.sqrt())?,
+            mean,
+        ))
+    }
+
+    /// A gamma distribution with the given mean and standard deviation, by default half the mean.
+    pub fn gamma(mean: f64, stdv: Option<f64>) -> Result<LengthDistribution, Box<dyn Error>> {
+        let stdv = stdv.unwrap_or(mean / 2.0);
+        let shape = (mean / stdv).powi(2);
+        Ok(LengthDistribution::Gamma(
+            Gamma::new(shape, mean / shape)?,
+            mean,
+        ))
+    }
+
+    /// A Weibull distribution with the given mean and shape.
+    pub fn weibull(mean: f64, shape: f64) -> Result<LengthDistribution, Box<dyn Error>> {
+        // the mean of a Weibull is scale * gamma(1 + 1 / shape)
+        let scale = mean / gamma_fac(1.0 / shape);
+        Ok(LengthDistribution::Weibull(
+            Weibull::new(scale, shape)?,
+            mean,
+        ))
+    }
+
+    /// Read a histogram of read lengths from a file. Each line is a bin, `start end count`, or a single length,
+    /// `length count`. Blank lines and lines starting with # are ignored.
+    pub fn histogram<P: AsRef<Path>>(path: P) -> Result<LengthDistribution, Box<dyn Error>> {
+        let contents = read_to_string(&path).map_err(|err| {
+            format!(
+                "Failed to read read length histogram {} - {err}",
+                path.as_ref().display()
+            )
+        })?;
+        let mut bins = vec![];
+        let mut counts = vec![];
+        for line in contents
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        {
+            let fields = line
+                .split_whitespace()
+                .map(|field| field.parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()
+                .map_err(|err| format!("Invalid read length histogram line {line} - {err}"))?;
+            match fields[..] {
+                [length, count] => {
+                    bins.push((length, length));
+                    counts.push(count);
+                }
+                [start, end, count] if start <= end => {
+                    bins.push((start, end));
+                    counts.push(count);
+                }
+                _ => return Err(format!("Invalid read length histogram line {line}").into()),
+            }
+        }
+        let weights = WeightedIndex::new(&counts)?;
+        let mean = bins
+            .iter()
+            .zip(&counts)
+            .map(|((start, end), count)| (start + end) / 2.0 * count)
+            .sum::<f64>()
+            / counts.iter().sum::<f64>();
+        Ok(LengthDistribution::Histogram {
+            bins,
+            weights,
+            mean,
+        })
+    }
+
+    /// The mean length of the distribution in bases.
+    pub fn mean(&self) -> f64 {
+        match self {
+            LengthDistribution::Skew { mean, .. }
+            | LengthDistribution::LogNormal(_, mean)
+            | LengthDistribution::Gamma(_, mean)
+            | LengthDistribution::Weibull(_, mean)
+            | LengthDistribution::Fixed(mean)
+            | LengthDistribution::Histogram { mean, .. } => *mean,
+            LengthDistribution::Empirical(lengths) => lengths.mean(),
+        }
+    }
+}
+
+impl SampleDist for LengthDistribution {
+    fn sample<R>(&self, rng: &mut R) -> f64
+    where
+        R: Rng,
+    {
+        match self {
+            // scale the F distribution about its mean, then move it to the read length mean
+            LengthDistribution::Skew {
+                f,
+                scale_factor,
+                mean,
+            } => (f.sample(rng) - F_MEAN) * (1.0 + scale_factor) + mean,
+            LengthDistribution::LogNormal(dist, _) => dist.sample(rng),
+            LengthDistribution::Gamma(dist, _) => dist.sample(rng),
+            LengthDistribution::Weibull(dist, _) => dist.sample(rng),
+            LengthDistribution::Fixed(length) => *length,
+            LengthDistribution::Histogram { bins, weights, .. } => {
+                let (start, end) = bins[weights.sample(rng)];
+                match start < end {
+                    true => rng.gen_range(start..end),
+                    false => start,
+                }
+            }
+            LengthDistribution::Empirical(lengths) => lengths.sample(rng),
+        }
+    }
+}
+
+/// The lengths of real reads, each simulated read being as long as one of them
+#[derive(Debug)]
+pub struct EmpiricalLengths {
+    lengths: Vec<f64>,
+}
+
+impl EmpiricalLengths {
+    /// Read the lengths of real reads from a FASTQ or FASTA, optionally gzipped, a sequencing summary, or a file of one
+    /// length per line. Reads of length 0 are skipped.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<EmpiricalLengths, Box<dyn Error>> {
+        let path = path.as_ref();
+        let mut lengths = vec![];
+        if path.is_fasta() {
+            let mut reader = parse_fastx_file(path)?;
+            while let Some(record) = reader.next() {
+                lengths.push(record?.num_bases() as f64);
+            }
+        } else {
+            let file = File::open(path)
+                .map_err(|err| format!("Failed to read read lengths {} - {err}", path.display()))?;
+            let reader: Box<dyn Read> = match path.extension().is_some_and(|ext| ext == "gz") {
+                true => Box::new(MultiGzDecoder::new(file)),
+                false => Box::new(file),
+            };
+            let mut length_column = None;
+            for line in BufReader::new(reader).lines() {
+                let line = line?;
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                // sequencing summaries are tab separated, and may have empty fields
+                let fields: Vec<&str> = match line.contains('\t') {
+                    true => line.split('\t').collect(),
+                    false => line.split_whitespace().collect(),
+                };
+                let column = match length_column {
+                    Some(column) => column,
+                    // a sequencing summary has a header naming its columns, a list of lengths has none
+                    None => match fields
+                        .iter()
+                        .position(|field| *field == SUMMARY_LENGTH_COLUMN)
+                    {
+                        Some(column) => {
+                            length_column = Some(column);
+                            continue;
+                        }
+                        None => *length_column.insert(0),
+                    },
+                };
+                let length: f64 = fields
+                    .get(column)
+                    .ok_or_else(|| format!("Read lengths line is missing columns - {line}"))?
+                    .parse()
+                    .map_err(|err| format!("Invalid read length in line {line} - {err}"))?;
+                lengths.push(length);
+            }
+        }
+        lengths.retain(|length| *length > 0.0);
+        if lengths.is_empty() {
+            return Err(format!("No read lengths found in {}", path.display()).into());
+        }
+        info!(
+            "Loaded {} read lengths from {}",
+            lengths.len(),
+            path.display()
+        );
+        Ok(EmpiricalLengths { lengths })
+    }
+
+    /// The mean length of the reads in bases.
+    pub fn mean(&self) -> f64 {
+        self.lengths.iter().sum::<f64>() / self.lengths.len() as f64
+    }
+}
+
+impl SampleDist for EmpiricalLengths {
+    fn sample<R>(&self, rng: &mut R) -> f64
+    where
+        R: Rng,
+    {
+        *self.lengths.choose(rng).unwrap()
+    }
+}
+
+/// The distribution of read lengths of a sample, drawing lengths in samples of signal
 #[derive(Debug)]
 pub struct ReadLengthDist {
-    dist: Vec<f64>,
+    distribution: LengthDistribution,
+    /// Shortest read in bases
+    min: f64,
+    /// Longest read in bases
+    max: f64,
+    samples_per_base: f64,
 }
 
 impl ReadLengthDist {
-    pub fn new(mean_read_length: f64) -> ReadLengthDist {
-        let dist = _create_skew_dist(mean_read_length, mean_read_length / 2.0_f64, 1.25, 10000.0);
-        ReadLengthDist { dist }
+    pub fn new(
+        distribution: LengthDistribution,
+        min: f64,
+        max: f64,
+        samples_per_base: f64,
+    ) -> ReadLengthDist {
+        ReadLengthDist {
+            distribution,
+            min,
+            max,
+            samples_per_base,
+        }
+    }
+
+    /// The mean read length in bases, before the lengths are clipped.
+    pub fn mean_bases(&self) -> f64 {
+        self.distribution.mean().clamp(self.min, self.max)
     }
 }
 
@@ -32,29 +319,6 @@ impl SampleDist for ReadLengthDist {
     where
         R: Rng,
     {
-        *self.dist.choose(rng).unwrap()
-    }
-}
-
-fn _create_skew_dist(mean: f64, sd: f64, skew: f64, size: f64) -> Vec<f64> {
-    let mut rng = thread_rng();
-
-    let df1 = 10.0_f64.powf((LOGLOG_SLOPE * skew.abs().log10()) + LOGLOG_INTERCEPT);
-    let f = FisherF::new(df1, DF2).unwrap();
-    let mut fsample: Vec<f64> = f.sample_iter(&mut rng).take(size as usize).collect();
-    fsample.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
-    let scaling_slope: f64 = skew.abs() * K1_SLOPE + K1_INTERCEPT;
-    let scaling_intercept: f64 = skew.abs() * K2_SLOPE + K2_INTERCEPT;
-    let scale_factor: f64 = (sd - scaling_intercept) / scaling_slope;
-    let fsample_mean: f64 = fsample.iter().sum::<f64>() / fsample.len() as f64;
-    fsample
-        .iter_mut()
-        .for_each(|i| *i = ((*i - fsample_mean) * scale_factor) + *i);
-    if skew < 0.0_f64 {
-        let fsample_mean: f64 = fsample.iter().sum::<f64>() / fsample.len() as f64;
-        fsample.iter_mut().for_each(|i| *i = fsample_mean - *i);
-    }
-    let fsample_mean: f64 = fsample.iter().sum::<f64>() / fsample.len() as f64;
-    fsample.iter_mut().for_each(|i| *i += mean + fsample_mean);
-    fsample
+        self.distribution.sample(rng).clamp(self.min, self.max) * self.samples_per_base
+    }
 }
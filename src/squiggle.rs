@@ -0,0 +1,271 @@
+//! The `icarust squiggle` subcommand, a native replacement for `python/make_squiggle.py`.
+//!
+//! Simulates squiggle from reference sequences with a pore model, writing a `.npy` array for each contig (or each amplicon
+//! of a primer scheme) and a `distributions.json` giving the relative weight of each array, which can then be served as a
+//! sample. The squiggle is at 4000 Hz, the same as the other squiggle Icarust reads from disk, and is written with the
+//! calibration of Icarust's output files so it reads back at the pore model's current.
+//!
+//! The noise and dwell come from the `[signal]` table of the simulation profile if one is given, and can be set on the
+//! command line, so the squiggle matches what Icarust would simulate from the same sequence.
+//!
+use std::error::Error;
+use std::fs::{create_dir_all, File};
+use std::path::{Path, PathBuf};
+
+use indicatif::{ProgressBar, ProgressStyle};
+use ndarray::ArrayView1;
+use ndarray_npy::write_npy;
+use needletail::parse_fastx_file;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rand_distr::{Distribution, SkewNormal};
+
+use crate::cli::SquiggleArgs;
+use crate::impl_services::data::{Weights, STATIC_SIGNAL_SAMPLE_RATE};
+use crate::pore_model::PoreModel;
+use crate::primer_scheme::collapse_amplicon_start_ends;
+use crate::r10_simulation::{self as r10_sim, R10Settings};
+use crate::Config;
+
+/// Sequence added either side of the barcode sequences, as python/utils.py does
+const BARCODE_FLANKS: [&str; 4] = [
+    "ACGTGCTAGCTAGGATCAGT",
+    "ATCGTT",
+    "ATCGCTAGCTA",
+    "ACTCGTGACACGT",
+];
+
+/// Run the squiggle subcommand, taking any noise and dwell settings not given on the command line from the simulation
+/// profile.
+pub fn run(args: &SquiggleArgs, config: Option<&Config>) -> Result<(), Box<dyn Error>> {
+    validate_args(args)?;
+    let model = PoreModel::from_file(&args.pore_model)?;
+    let samples_per_base = STATIC_SIGNAL_SAMPLE_RATE / args.bases_per_second;
+    let mut signal = config
+        .map(|config| config.get_signal_config())
+        .unwrap_or_default();
+    signal.level_noise = args.level_noise.or(signal.level_noise);
+    signal.dwell_distribution = args
+        .dwell_distribution
+        .clone()
+        .or(signal.dwell_distribution);
+    signal.dwell_stdv = args.dwell_stdv.or(signal.dwell_stdv);
+    if signal.get_level_noise() < 0.0 || signal.get_dwell_stdv(samples_per_base) <= 0.0 {
+        return Err(
+            "level_noise must not be negative, and dwell_stdv must be greater than 0.".into(),
+        );
+    }
+    let profile = r10_sim::get_sim_profile(
+        r10_sim::SimType::R10,
+        samples_per_base,
+        signal.get_dwell_distribution(samples_per_base),
+        signal.get_level_noise(),
+    );
+    let mut rng: StdRng = match args.seed {
+        Some(seed) => SeedableRng::seed_from_u64(seed),
+        None => SeedableRng::from_entropy(),
+    };
+    let amplicons = match &args.bed_file {
+        Some(bed_file) => Some(collapse_amplicon_start_ends(bed_file)?),
+        None => None,
+    };
+
+    // the length of each contig or amplicon, for the distributions
+    let mut seq_lens: Vec<(String, usize)> = vec![];
+    for (index, reference) in args.reference_files.iter().enumerate() {
+        let barcode_seqs = match &args.barcode {
+            Some(barcodes) => Some(get_barcode_seq(&args.barcode_dir, &barcodes[index])?),
+            None => None,
+        };
+        let mut reader = parse_fastx_file(reference)?;
+        while let Some(record) = reader.next() {
+            let record = record?;
+            let id = String::from_utf8_lossy(record.id()).to_string();
+            let name = id.split_whitespace().next().unwrap_or_default().to_string();
+            info!("Generating squiggle for {name}");
+            let seq = clean_sequence(&record.seq());
+            match &amplicons {
+                Some(amplicons) => {
+                    for amplicon in amplicons {
+                        let amp_seq =
+                            &seq[amplicon.start.min(seq.len())..amplicon.end.min(seq.len())];
+                        info!(
+                            "Amplicon {} spans reference from {}: {}",
+                            amplicon.name, amplicon.start, amplicon.end
+                        );
+                        seq_lens.push((amplicon.name.clone(), amp_seq.len()));
+                        let squiggle_path = args
+                            .out_dir
+                            .join(format!("{name}_{}.squiggle.npy", amplicon.name));
+                        let amp_seq = append_barcode_sequence(amp_seq, barcode_seqs.as_ref());
+                        write_squiggle(&amp_seq, &squiggle_path, &model, &profile, &mut rng)?;
+                    }
+                }
+                None => {
+                    seq_lens.push((name.clone(), seq.len()));
+                    let squiggle_path = args.out_dir.join(format!("{name}.squiggle.npy"));
+                    let seq = append_barcode_sequence(&seq, barcode_seqs.as_ref());
+                    write_squiggle(&seq, &squiggle_path, &model, &profile, &mut rng)?;
+                }
+            }
+        }
+    }
+
+    info!("Creating distributions file.");
+    let distributions = match args.skew == 0.0 {
+        true => {
+            seq_lens.sort_by(|a, b| a.0.cmp(&b.0));
+            Weights {
+                weights: seq_lens.iter().map(|(_, len)| *len).collect(),
+                names: seq_lens.into_iter().map(|(name, _)| name).collect(),
+            }
+        }
+        false => {
+            let skew_normal = SkewNormal::new(0.0, 7.0, args.skew)?;
+            Weights {
+                weights: seq_lens
+                    .iter()
+                    .map(|_| skew_normal.sample(&mut rng).round().abs() as usize)
+                    .collect(),
+                names: seq_lens.into_iter().map(|(name, _)| name).collect(),
+            }
+        }
+    };
+    write_distribution_json(&args.out_dir.join("distributions.json"), distributions)
+}
+
+/// Check the arguments make sense together, and create the output directory if it doesn't exist.
+fn validate_args(args: &SquiggleArgs) -> Result<(), Box<dyn Error>> {
+    if let Some(bed_file) = &args.bed_file {
+        if args.reference_files.len() > 1 {
+            return Err("If a BED file is provided, please only specify one reference".into());
+        }
+        if !bed_file.exists() {
+            return Err(format!(
+                "{} not found. Please double check provided path...",
+                bed_file.display()
+            )
+            .into());
+        }
+    }
+    if let Some(barcodes) = &args.barcode {
+        if barcodes.len() != args.reference_files.len() {
+            return Err("Provided number of barcodes must equal number of reference files.".into());
+        }
+        for barcode in barcodes {
+            barcode_file_path(&args.barcode_dir, barcode)?;
+        }
+    }
+    if !args.out_dir.exists() {
+        warn!(
+            "{} does not exist, attempting to create it",
+            args.out_dir.display()
+        );
+        create_dir_all(&args.out_dir)?;
+    }
+    info!("Validations passed");
+    Ok(())
+}
+
+/// Uppercase a sequence and remove anything that isn't A, C, G or T.
+fn clean_sequence(seq: &[u8]) -> Vec<u8> {
+    seq.iter()
+        .map(|base| base.to_ascii_uppercase())
+        .filter(|base| matches!(base, b'A' | b'C' | b'G' | b'T'))
+        .collect()
+}
+
+/// Find the FASTA file for a barcode in the barcode directory, as either Barcode01.fasta or Barcode01_gen.fasta.
+fn barcode_file_path(barcode_dir: &Path, barcode: &str) -> Result<PathBuf, Box<dyn Error>> {
+    [
+        format!("{barcode}.fasta"),
+        format!("{barcode}_gen.fasta"),
+    ]
+    .iter()
+    .map(|file_name| barcode_dir.join(file_name))
+    .find(|path| path.exists())
+    .ok_or_else(|| {
+        format!(
+            "No FASTA for {barcode} found in {}. Looking for the barcoding directory in the base icarust source code directory.",
+            barcode_dir.display()
+        )
+        .into()
+    })
+}
+
+/// Read the sequences for the start and end of a barcode, the first two records of its FASTA file.
+fn get_barcode_seq(
+    barcode_dir: &Path,
+    barcode: &str,
+) -> Result<(Vec<u8>, Vec<u8>), Box<dyn Error>> {
+    let path = barcode_file_path(barcode_dir, barcode)?;
+    let mut reader = parse_fastx_file(&path)?;
+    let mut seqs = vec![];
+    while let Some(record) = reader.next() {
+        seqs.push(record?.seq().to_vec());
+    }
+    match seqs.len() {
+        2.. => Ok((seqs.swap_remove(0), seqs.swap_remove(0))),
+        _ => Err(format!("{} should have two barcode sequences", path.display()).into()),
+    }
+}
+
+/// Add the barcode sequences, and the sequence flanking them, to the start and end of a sequence.
+fn append_barcode_sequence(seq: &[u8], barcode_seqs: Option<&(Vec<u8>, Vec<u8>)>) -> Vec<u8> {
+    match barcode_seqs {
+        Some((barcode_seq_1, barcode_seq_2)) => [
+            BARCODE_FLANKS[0].as_bytes(),
+            barcode_seq_1,
+            BARCODE_FLANKS[1].as_bytes(),
+            seq,
+            BARCODE_FLANKS[2].as_bytes(),
+            barcode_seq_2,
+            BARCODE_FLANKS[3].as_bytes(),
+        ]
+        .concat(),
+        None => seq.to_vec(),
+    }
+}
+
+/// Simulate the squiggle for a sequence and save it as a .npy array, unless the file already exists.
+fn write_squiggle(
+    seq: &[u8],
+    squiggle_path: &Path,
+    model: &PoreModel,
+    profile: &R10Settings,
+    rng: &mut StdRng,
+) -> Result<(), Box<dyn Error>> {
+    if squiggle_path.exists() {
+        warn!(
+            "File with name {} already exists. Skipping...",
+            squiggle_path.display()
+        );
+        return Ok(());
+    }
+    let sty = ProgressStyle::with_template(
+        "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}",
+    )
+    .unwrap()
+    .progress_chars("##-");
+    let pb = ProgressBar::new(seq.len().saturating_sub(model.k - 1) as u64);
+    pb.set_style(sty);
+    let signal = r10_sim::sequence_to_signal(model, seq, profile, rng, &pb);
+    pb.finish_with_message("done");
+    write_npy(squiggle_path, &ArrayView1::from(&signal))?;
+    Ok(())
+}
+
+/// Write out the distributions, appending them to the distributions already in the file if it exists.
+fn write_distribution_json(file_path: &Path, distributions: Weights) -> Result<(), Box<dyn Error>> {
+    let distributions = match file_path.exists() {
+        true => {
+            let mut existing: Weights = serde_json::from_reader(File::open(file_path)?)?;
+            existing.weights.extend(distributions.weights);
+            existing.names.extend(distributions.names);
+            existing
+        }
+        false => distributions,
+    };
+    serde_json::to_writer(File::create(file_path)?, &distributions)?;
+    Ok(())
+}
@@ -0,0 +1,353 @@
+//! Reads VCF files of variants and applies them to reference sequences, so reads are simulated from a genome that differs
+//! from the reference.
+//!
+//! SNVs, indels and the simple symbolic structural variants `<DEL>`, `<INV>` and `<DUP>` are applied, anything else is
+//! skipped. If the first sample column has a GT field, each allele of the genotype is applied to its own haplotype, so a
+//! diploid VCF gives two haplotypes, phased if the VCF is. Unphased genotypes are applied as if they were phased. Records
+//! without a genotype are applied to every haplotype. Records that don't PASS, or that overlap a variant already applied to
+//! the haplotype, are skipped.
+//!
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+use flate2::read::MultiGzDecoder;
+
+use crate::barcodes::reverse_complement;
+
+/// What an alternate allele does to the reference
+#[derive(Debug, Clone)]
+enum Allele {
+    /// Replace the reference allele with this sequence
+    Sequence(Vec<u8>),
+    /// Delete the span of the variant
+    Deletion,
+    /// Reverse complement the span of the variant
+    Inversion,
+    /// Repeat the span of the variant in tandem
+    Duplication,
+    /// A symbolic allele we can't apply
+    Unsupported,
+}
+
+/// A record of the VCF
+#[derive(Debug, Clone)]
+struct Variant {
+    /// Start of the reference span the variant changes, 0 based
+    start: usize,
+    /// End of the reference span the variant changes, exclusive
+    end: usize,
+    alleles: Vec<Allele>,
+    /// Index of the allele on each haplotype, None if the genotype is missing
+    genotype: Option<Vec<Option<usize>>>,
+}
+
+/// The variants in a VCF, by chromosome
+#[derive(Debug)]
+pub struct Variants {
+    variants: HashMap<String, Vec<Variant>>,
+    /// Number of haplotypes the variants are applied to, the most alleles in any genotype
+    pub num_haplotypes: usize,
+}
+
+/// A reference sequence with the variants of one haplotype applied
+pub struct Haplotype<'a> {
+    pub sequence: Cow<'a, [u8]>,
+    pub shifts: Shifts,
+}
+
+/// How positions move between the reference and a haplotype. Holds the start and end of each applied variant on the
+/// reference, and the total change in length up to and including it.
+#[derive(Debug, Clone, Default)]
+pub struct Shifts(Vec<(usize, usize, isize)>);
+
+impl Shifts {
+    /// Move a position on the reference to the same position on the haplotype.
+    pub fn lift(&self, position: usize) -> usize {
+        let applied = self.0.partition_point(|(_, end, _)| *end <= position);
+        (position as isize + self.shift_before(applied)).max(0) as usize
+    }
+
+    /// Move a position on the haplotype back to the reference. None if the base was put there by a variant, so it has no
+    /// reference position.
+    pub fn to_reference(&self, position: usize) -> Option<usize> {
+        // variants that end on the haplotype at or before the position
+        let applied = self
+            .0
+            .partition_point(|(_, end, shift)| (*end as isize + shift) as usize <= position);
+        let shift = self.shift_before(applied);
+        match self.0.get(applied) {
+            Some((start, _, _)) if (*start as isize + shift) as usize <= position => None,
+            _ => Some((position as isize - shift) as usize),
+        }
+    }
+
+    /// The change in length from the first `applied` variants.
+    fn shift_before(&self, applied: usize) -> isize {
+        match applied {
+            0 => 0,
+            _ => self.0[applied - 1].2,
+        }
+    }
+}
+
+impl Haplotype<'_> {
+    /// The reference sequence, with no variants applied.
+    pub fn reference(sequence: &[u8]) -> Haplotype<'_> {
+        Haplotype {
+            sequence: Cow::Borrowed(sequence),
+            shifts: Shifts::default(),
+        }
+    }
+
+    /// Move a position on the reference to the same position on this haplotype.
+    pub fn lift(&self, position: usize) -> usize {
+        self.shifts.lift(position)
+    }
+}
+
+impl Variants {
+    /// Read the variants from a VCF, which may be gzipped.
+    pub fn from_file(path: &Path) -> Result<Variants, Box<dyn Error>> {
+        let file = File::open(path)?;
+        let reader: Box<dyn Read> = match path.extension().is_some_and(|ext| ext == "gz") {
+            true => Box::new(MultiGzDecoder::new(file)),
+            false => Box::new(file),
+        };
+        let mut variants: HashMap<String, Vec<Variant>> = HashMap::new();
+        let mut num_haplotypes = 1;
+        for line in BufReader::new(reader).lines() {
+            let line = line?;
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() < 8 {
+                return Err(format!("VCF line has fewer than 8 columns - {line}").into());
+            }
+            if !matches!(fields[6], "PASS" | ".") {
+                continue;
+            }
+            let Some(variant) = parse_record(&fields)? else {
+                warn!(
+                    "Skipping variant at position 0 of {}, as it has no base before the reference",
+                    fields[0]
+                );
+                continue;
+            };
+            if let Some(genotype) = &variant.genotype {
+                num_haplotypes = num_haplotypes.max(genotype.len());
+            }
+            variants
+                .entry(fields[0].to_string())
+                .or_default()
+                .push(variant);
+        }
+        for chrom_variants in variants.values_mut() {
+            chrom_variants.sort_by_key(|variant| variant.start);
+        }
+        Ok(Variants {
+            variants,
+            num_haplotypes,
+        })
+    }
+
+    /// Apply the variants on a chromosome to its sequence, giving the sequence of each haplotype.
+    pub fn haplotypes<'a>(&self, chrom: &str, sequence: &'a [u8]) -> Vec<Haplotype<'a>> {
+        let Some(variants) = self.variants.get(chrom) else {
+            return (0..self.num_haplotypes)
+                .map(|_| Haplotype::reference(sequence))
+                .collect();
+        };
+        (0..self.num_haplotypes)
+            .map(|haplotype| apply_variants(variants, haplotype, sequence))
+            .collect()
+    }
+}
+
+/// Parse a VCF record into the span it changes, its alleles and genotype. Returns None for a telomeric record at position 0
+/// that isn't symbolic, as it starts before the reference.
+fn parse_record(fields: &[&str]) -> Result<Option<Variant>, Box<dyn Error>> {
+    let position: usize = fields[1].parse()?;
+    let reference = fields[3];
+    let alleles: Vec<Allele> = fields[4]
+        .split(',')
+        .map(|alt| match alt.to_uppercase().as_str() {
+            "<DEL>" => Allele::Deletion,
+            "<INV>" => Allele::Inversion,
+            "<DUP>" | "<DUP:TANDEM>" => Allele::Duplication,
+            x if x.starts_with('<') || x.contains(['[', ']', '*', '.']) => Allele::Unsupported,
+            x => Allele::Sequence(x.as_bytes().to_vec()),
+        })
+        .collect();
+    let symbolic = alleles
+        .iter()
+        .any(|allele| !matches!(allele, Allele::Sequence(_)));
+    let info = |key: &str| {
+        fields[7]
+            .split(';')
+            .find_map(|entry| entry.strip_prefix(key)?.strip_prefix('='))
+    };
+    // symbolic alleles start after the padding base, and run to END
+    let (start, end) = match (symbolic, info("END"), info("SVLEN")) {
+        (true, Some(end), _) => (position, end.parse()?),
+        (true, None, Some(sv_len)) => {
+            (position, position + sv_len.parse::<isize>()?.unsigned_abs())
+        }
+        _ => match position.checked_sub(1) {
+            Some(start) => (start, start + reference.len()),
+            None => return Ok(None),
+        },
+    };
+    let genotype = match (fields.get(8), fields.get(9)) {
+        (Some(format), Some(sample)) => format
+            .split(':')
+            .position(|key| key == "GT")
+            .and_then(|index| sample.split(':').nth(index))
+            .map(|genotype| {
+                genotype
+                    .split(['|', '/'])
+                    .map(|allele| allele.parse().ok())
+                    .collect()
+            }),
+        _ => None,
+    };
+    Ok(Some(Variant {
+        start,
+        end: end.max(start),
+        alleles,
+        genotype,
+    }))
+}
+
+/// Apply the alleles of one haplotype to a sequence.
+fn apply_variants<'a>(variants: &[Variant], haplotype: usize, sequence: &'a [u8]) -> Haplotype<'a> {
+    let mut applied: Vec<u8> = Vec::with_capacity(sequence.len());
+    let mut shifts = vec![];
+    let mut shift: isize = 0;
+    let mut last_end = 0;
+    for variant in variants {
+        let allele_index = match &variant.genotype {
+            Some(genotype) => genotype.get(haplotype).copied().flatten(),
+            None => Some(1),
+        };
+        let allele = match allele_index.and_then(|index| index.checked_sub(1)) {
+            Some(index) => variant.alleles.get(index).unwrap_or(&Allele::Unsupported),
+            None => continue,
+        };
+        if variant.start < last_end || variant.end > sequence.len() {
+            warn!(
+                "Skipping variant at {} as it overlaps another variant or runs off the end of the sequence",
+                variant.start + 1
+            );
+            continue;
+        }
+        let span = &sequence[variant.start..variant.end];
+        let replacement: Cow<[u8]> = match allele {
+            Allele::Sequence(alt) => Cow::Borrowed(alt),
+            Allele::Deletion => Cow::Borrowed(&[]),
+            Allele::Inversion => {
+                Cow::Owned(reverse_complement(&String::from_utf8_lossy(span)).into_bytes())
+            }
+            Allele::Duplication => Cow::Owned([span, span].concat()),
+            Allele::Unsupported => continue,
+        };
+        applied.extend_from_slice(&sequence[last_end..variant.start]);
+        applied.extend_from_slice(&replacement);
+        shift += replacement.len() as isize - span.len() as isize;
+        shifts.push((variant.start, variant.end, shift));
+        last_end = variant.end;
+    }
+    applied.extend_from_slice(&sequence[last_end..]);
+    Haplotype {
+        sequence: Cow::Owned(applied),
+        shifts: Shifts(shifts),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REFERENCE: &[u8] = b"ACGTACGTAC";
+
+    /// Apply a single variant, on every haplotype, to the reference.
+    fn apply(start: usize, end: usize, allele: Allele) -> Haplotype<'static> {
+        let variant = Variant {
+            start,
+            end,
+            alleles: vec![allele],
+            genotype: None,
+        };
+        apply_variants(&[variant], 0, REFERENCE)
+    }
+
+    #[test]
+    fn snv_keeps_positions() {
+        let haplotype = apply(2, 3, Allele::Sequence(b"T".to_vec()));
+        assert_eq!(haplotype.sequence.as_ref(), b"ACTTACGTAC");
+        assert_eq!(haplotype.lift(5), 5);
+        assert_eq!(haplotype.shifts.to_reference(1), Some(1));
+        assert_eq!(haplotype.shifts.to_reference(5), Some(5));
+        // the changed base isn't the reference base
+        assert_eq!(haplotype.shifts.to_reference(2), None);
+    }
+
+    #[test]
+    fn insertion_shifts_later_positions() {
+        let haplotype = apply(2, 3, Allele::Sequence(b"GTT".to_vec()));
+        assert_eq!(haplotype.sequence.as_ref(), b"ACGTTTACGTAC");
+        assert_eq!(haplotype.lift(1), 1);
+        assert_eq!(haplotype.lift(3), 5);
+        assert_eq!(haplotype.shifts.to_reference(1), Some(1));
+        assert_eq!(haplotype.shifts.to_reference(5), Some(3));
+        assert_eq!(haplotype.shifts.to_reference(11), Some(9));
+    }
+
+    #[test]
+    fn inserted_bases_have_no_reference_position() {
+        let haplotype = apply(2, 3, Allele::Sequence(b"GTT".to_vec()));
+        for position in 2..5 {
+            assert_eq!(haplotype.shifts.to_reference(position), None);
+        }
+    }
+
+    #[test]
+    fn deletion_shifts_later_positions() {
+        let haplotype = apply(2, 5, Allele::Sequence(b"G".to_vec()));
+        assert_eq!(haplotype.sequence.as_ref(), b"ACGCGTAC");
+        assert_eq!(haplotype.lift(5), 3);
+        assert_eq!(haplotype.shifts.to_reference(1), Some(1));
+        assert_eq!(haplotype.shifts.to_reference(3), Some(5));
+        assert_eq!(haplotype.shifts.to_reference(7), Some(9));
+
+        let haplotype = apply(2, 5, Allele::Deletion);
+        assert_eq!(haplotype.sequence.as_ref(), b"ACCGTAC");
+        assert_eq!(haplotype.lift(5), 2);
+        assert_eq!(haplotype.shifts.to_reference(1), Some(1));
+        assert_eq!(haplotype.shifts.to_reference(2), Some(5));
+    }
+
+    #[test]
+    fn positions_outside_variants_round_trip() {
+        let haplotype = apply(2, 5, Allele::Sequence(b"GTTTT".to_vec()));
+        for position in (0..2).chain(5..REFERENCE.len()) {
+            assert_eq!(
+                haplotype.shifts.to_reference(haplotype.lift(position)),
+                Some(position)
+            );
+        }
+    }
+
+    #[test]
+    fn position_zero_is_skipped() {
+        let fields = ["chr1", "0", ".", "A", "T", ".", "PASS", "."];
+        assert!(parse_record(&fields).unwrap().is_none());
+        let fields = ["chr1", "1", ".", "A", "T", ".", "PASS", "."];
+        let variant = parse_record(&fields).unwrap().unwrap();
+        assert_eq!((variant.start, variant.end), (0, 1));
+    }
+}